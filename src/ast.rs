@@ -1,5 +1,11 @@
 use crate::parser::common::Type;
-use std::{borrow::Cow, str::FromStr};
+use std::{
+    borrow::Cow,
+    fmt,
+    hash::{Hash, Hasher},
+    ops::Range,
+    str::FromStr,
+};
 use thiserror::Error;
 
 pub use self::{fold::Folder, visitor::Visitor};
@@ -41,6 +47,10 @@ pub struct ClassMember {
     pub visibility: Option<Visibility>,
     pub ty: Type,
     pub init_expr: Option<Box<Expression>>,
+    /// Declared with `const`, so it can only be written while the object is initialised.
+    pub constant: bool,
+    /// Configuration constant deciding whether this member is compiled, e.g. `CONFIG::debug`.
+    pub condition: Option<QualifiedName>,
 }
 
 #[derive(Debug)]
@@ -49,48 +59,304 @@ pub struct Function {
     pub visibility: Visibility,
     pub arguments: Vec<Argument>,
     pub return_type: Type,
-    pub block: Vec<Box<Statement>>,
+    pub block: Vec<Statement>,
+    /// Configuration constant deciding whether this function is compiled, e.g. `CONFIG::debug`.
+    pub condition: Option<QualifiedName>,
 }
 
-#[derive(Debug)]
+#[derive(Clone, Debug, PartialEq)]
 pub struct Argument {
     pub name: String,
     pub value_type: Type,
 }
 
-#[derive(Debug, PartialEq)]
+/// A name qualified by a namespace, e.g. `CONFIG::debug`.
+///
+/// Two names are equal if their namespace and name are, no matter where they were written.
+#[derive(Clone, Debug)]
+pub struct QualifiedName {
+    pub namespace: String,
+    pub name: String,
+    /// Byte range of the name in the source, used to point diagnostics at it.
+    pub span: Range<usize>,
+}
+
+impl PartialEq for QualifiedName {
+    fn eq(&self, other: &Self) -> bool {
+        self.namespace == other.namespace && self.name == other.name
+    }
+}
+
+impl Eq for QualifiedName {}
+
+impl Hash for QualifiedName {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.namespace.hash(state);
+        self.name.hash(state);
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
 pub enum Statement {
     Variable {
         name: String,
         var_type: Type,
         value: Box<Expression>,
     },
+    // A block of statements.
+    Block(Vec<Statement>),
+    // An if statement, else if is an if statement in the else branch.
+    If {
+        condition: Box<Expression>,
+        body: Box<Statement>,
+        else_body: Option<Box<Statement>>,
+    },
+    // A while loop.
+    While {
+        condition: Box<Expression>,
+        body: Box<Statement>,
+    },
+    // A do-while loop, the body runs at least once.
+    DoWhile {
+        body: Box<Statement>,
+        condition: Box<Expression>,
+    },
+    // A C-style for loop, every part of the head is optional.
+    For {
+        init: Option<Box<Statement>>,
+        condition: Option<Box<Expression>>,
+        update: Option<Box<Expression>>,
+        body: Box<Statement>,
+    },
+    // A for-in loop over the property names of an object, or over their values for for-each-in.
+    ForIn {
+        each: bool,
+        variable: ForInVariable,
+        object: Box<Expression>,
+        body: Box<Statement>,
+    },
+    // A switch statement, cases fall through into the next one unless they break.
+    Switch {
+        discriminant: Box<Expression>,
+        cases: Vec<SwitchCase>,
+    },
+    // A statement with a label, which break and continue can refer to.
+    Labeled {
+        label: String,
+        body: Box<Statement>,
+    },
+    // A try statement, with any number of catch clauses and an optional finally block.
+    Try {
+        body: Vec<Statement>,
+        catches: Vec<CatchClause>,
+        finally: Option<Vec<Statement>>,
+    },
+    // Throws a value as an exception.
+    Throw(Box<Expression>),
+    // Returns from the function, with an optional value.
+    Return(Option<Box<Expression>>),
+    // A break, with an optional label.
+    Break(Option<String>),
+    // A continue, with an optional label.
+    Continue(Option<String>),
+    // An expression that is evaluated for its side effects.
+    Expression(Box<Expression>),
+    // A nested function declaration, which is a local variable that holds the function.
+    Function(Box<LocalFunction>),
+    // A block that is only compiled if a configuration constant is true.
+    Config {
+        condition: QualifiedName,
+        block: Vec<Statement>,
+    },
+}
+
+/// A function defined inside another function, either by a function expression or a nested function declaration.
+#[derive(Clone, Debug, PartialEq)]
+pub struct LocalFunction {
+    /// Function expressions can be anonymous, declarations always have a name.
+    pub name: Option<String>,
+    pub arguments: Vec<Argument>,
+    pub return_type: Type,
+    pub block: Vec<Statement>,
+}
+
+/// Where a for-in loop stores each property name or value.
+#[derive(Clone, Debug, PartialEq)]
+pub enum ForInVariable {
+    /// `for (var name: Type in object)`, which declares a local variable.
+    Declaration { name: String, var_type: Type },
+    /// `for (name in object)`, which assigns to a variable that is declared elsewhere.
+    Target(Box<Expression>),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct SwitchCase {
+    /// The value to compare against, or `None` for the default case.
+    pub value: Option<Box<Expression>>,
+    pub body: Vec<Statement>,
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Clone, Debug, PartialEq)]
+pub struct CatchClause {
+    pub variable: String,
+    /// The type of exceptions that are caught, `*` catches everything.
+    pub var_type: Type,
+    pub body: Vec<Statement>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
 pub enum Expression {
     // A integer literal,
     Integer(i32),
+    // A integer literal too big to fit into an int.
+    Uint(u32),
+    // A floating point literal.
+    Number(f64),
     // A string literal.
     String(String),
     // A boolean,
     Bool(bool),
+    // A regular expression literal.
+    RegExp {
+        pattern: String,
+        flags: String,
+    },
+    // An array literal, the holes left by elisions like `[1, , 3]` are `None`.
+    Array(Vec<Option<Box<Expression>>>),
+    // An object literal, with the names and values of its properties.
+    Object(Vec<(String, Box<Expression>)>),
     // A variable.
     Variable(String),
+    // The `this` object of a method.
+    This,
+    // The `super` of a method, which can only be used to reach the properties and the constructor of the super
+    // class.
+    Super,
+    // The null literal.
+    Null,
+    // A namespace qualified name, e.g. a configuration constant.
+    QualifiedName(QualifiedName),
+    // A unary operation.
+    UnaryOperation {
+        operator: UnaryOperator,
+        value: Box<Expression>,
+    },
     // A binary operation.
     BinaryOperation {
         lhs: Box<Expression>,
         operator: Operator,
         rhs: Box<Expression>,
     },
+    // A property access with a fixed name, `object.name`.
+    Property {
+        object: Box<Expression>,
+        name: String,
+    },
+    // A computed property access, `object[index]`.
+    Index {
+        object: Box<Expression>,
+        index: Box<Expression>,
+    },
+    // A function expression, which creates a closure over the enclosing function.
+    Function(Box<LocalFunction>),
+    // A call of a function or method.
+    Call {
+        function: Box<Expression>,
+        arguments: Vec<Expression>,
+    },
+    // Constructs a new instance of a class, `new class(arguments)`.
+    New {
+        class: Box<Expression>,
+        arguments: Vec<Expression>,
+    },
+    // A conditional expression, `condition ? if_true : if_false`.
+    Ternary {
+        condition: Box<Expression>,
+        if_true: Box<Expression>,
+        if_false: Box<Expression>,
+    },
+    // An increment or decrement, which results in the old value unless it is a prefix operator.
+    Update {
+        operator: UpdateOperator,
+        prefix: bool,
+        target: Box<Expression>,
+    },
+    // An assignment, the operator of a compound assignment like `+=` is applied to the old value first.
+    Assignment {
+        target: Box<Expression>,
+        operator: Option<Operator>,
+        value: Box<Expression>,
+    },
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Clone, Debug, PartialEq)]
 pub enum Operator {
     Add,
     Sub,
     Mul,
     Div,
+    Mod,
+    BitAnd,
+    BitOr,
+    BitXor,
+    LeftShift,
+    RightShift,
+    UnsignedRightShift,
+    Less,
+    LessEqual,
+    Greater,
+    GreaterEqual,
+    Equal,
+    NotEqual,
+    StrictEqual,
+    StrictNotEqual,
+    // Logical operators only evaluate the right hand side if the left hand side does not decide the result.
+    And,
+    Or,
+}
+
+impl Operator {
+    /// Whether this operator compares its operands, resulting in a Boolean.
+    pub fn is_comparison(&self) -> bool {
+        matches!(
+            self,
+            Self::Less
+                | Self::LessEqual
+                | Self::Greater
+                | Self::GreaterEqual
+                | Self::Equal
+                | Self::NotEqual
+                | Self::StrictEqual
+                | Self::StrictNotEqual
+        )
+    }
+
+    /// Whether this operator works on the bits of its operands, which are converted into 32-bit integers.
+    pub fn is_bitwise(&self) -> bool {
+        matches!(
+            self,
+            Self::BitAnd
+                | Self::BitOr
+                | Self::BitXor
+                | Self::LeftShift
+                | Self::RightShift
+                | Self::UnsignedRightShift
+        )
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum UnaryOperator {
+    Not,
+    BitNot,
+    Negate,
+    Plus,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum UpdateOperator {
+    Increment,
+    Decrement,
 }
 
 impl FromStr for Visibility {
@@ -106,18 +372,46 @@ impl FromStr for Visibility {
     }
 }
 
+impl fmt::Display for QualifiedName {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}::{}", self.namespace, self.name)
+    }
+}
+
 impl<'a> TryFrom<&'a Expression> for Cow<'a, String> {
     type Error = Error;
 
     fn try_from(value: &'a Expression) -> Result<Self, Self::Error> {
         match value {
             Expression::Integer(v) => Ok(Cow::Owned(v.to_string())),
+            Expression::Uint(v) => Ok(Cow::Owned(v.to_string())),
+            Expression::Number(v) => Ok(Cow::Owned(number_to_string(*v))),
             Expression::String(v) => Ok(Cow::Borrowed(v)),
             _ => Err(Error::CannotConvertToString),
         }
     }
 }
 
+/// Converts a Number into a string the same way the AVM2 does.
+pub fn number_to_string(value: f64) -> String {
+    if value.is_nan() {
+        "NaN".to_owned()
+    } else if value.is_infinite() {
+        if value > 0.0 { "Infinity" } else { "-Infinity" }.to_owned()
+    } else if value != 0.0 && (value.abs() >= 1e21 || value.abs() < 1e-6) {
+        // Rust omits the sign of positive exponents, ECMAScript does not.
+        let formatted = format!("{:e}", value);
+        if formatted.contains("e-") {
+            formatted
+        } else {
+            formatted.replace('e', "e+")
+        }
+    } else {
+        // Rust already prints the shortest representation that round trips, like ECMAScript.
+        format!("{}", value)
+    }
+}
+
 pub mod visitor {
     use super::*;
 
@@ -155,13 +449,78 @@ pub mod visitor {
                 operator: _,
                 rhs,
             } => {
-                visitor.visit_expression(&lhs)?;
-                visitor.visit_expression(&rhs)?;
+                visitor.visit_expression(lhs)?;
+                visitor.visit_expression(rhs)?;
+            }
+            Expression::UnaryOperation { operator: _, value } => visitor.visit_expression(value)?,
+            Expression::Array(elements) => {
+                for element in elements.iter().flatten() {
+                    visitor.visit_expression(element)?;
+                }
+            }
+            Expression::Object(properties) => {
+                for (_, value) in properties {
+                    visitor.visit_expression(value)?;
+                }
+            }
+            Expression::Property { object, name: _ } => visitor.visit_expression(object)?,
+            Expression::Index { object, index } => {
+                visitor.visit_expression(object)?;
+                visitor.visit_expression(index)?;
+            }
+            Expression::Function(function) => {
+                for v in &function.block {
+                    visitor.visit_statement(v)?;
+                }
+            }
+            Expression::Call {
+                function,
+                arguments,
+            } => {
+                visitor.visit_expression(function)?;
+                for argument in arguments {
+                    visitor.visit_expression(argument)?;
+                }
+            }
+            Expression::New { class, arguments } => {
+                visitor.visit_expression(class)?;
+                for argument in arguments {
+                    visitor.visit_expression(argument)?;
+                }
+            }
+            Expression::Ternary {
+                condition,
+                if_true,
+                if_false,
+            } => {
+                visitor.visit_expression(condition)?;
+                visitor.visit_expression(if_true)?;
+                visitor.visit_expression(if_false)?;
+            }
+            Expression::Update {
+                operator: _,
+                prefix: _,
+                target,
+            } => visitor.visit_expression(target)?,
+            Expression::Assignment {
+                target,
+                operator: _,
+                value,
+            } => {
+                visitor.visit_expression(target)?;
+                visitor.visit_expression(value)?;
             }
             Expression::Integer(_) => {}
+            Expression::Uint(_) => {}
+            Expression::Number(_) => {}
             Expression::Bool(_) => {}
+            Expression::RegExp { .. } => {}
             Expression::String(_) => {}
             Expression::Variable(_) => {}
+            Expression::This => {}
+            Expression::Super => {}
+            Expression::Null => {}
+            Expression::QualifiedName(_) => {}
         }
 
         Ok(())
@@ -176,7 +535,105 @@ pub mod visitor {
                 name: _,
                 var_type: _,
                 value,
-            } => visitor.visit_expression(&value)?,
+            } => visitor.visit_expression(value)?,
+            Statement::Block(block)
+            | Statement::Config {
+                condition: _,
+                block,
+            } => {
+                for v in block {
+                    visitor.visit_statement(v)?;
+                }
+            }
+            Statement::If {
+                condition,
+                body,
+                else_body,
+            } => {
+                visitor.visit_expression(condition)?;
+                visitor.visit_statement(body)?;
+                if let Some(else_body) = else_body {
+                    visitor.visit_statement(else_body)?;
+                }
+            }
+            Statement::While { condition, body } | Statement::DoWhile { body, condition } => {
+                visitor.visit_expression(condition)?;
+                visitor.visit_statement(body)?;
+            }
+            Statement::For {
+                init,
+                condition,
+                update,
+                body,
+            } => {
+                if let Some(init) = init {
+                    visitor.visit_statement(init)?;
+                }
+                if let Some(condition) = condition {
+                    visitor.visit_expression(condition)?;
+                }
+                if let Some(update) = update {
+                    visitor.visit_expression(update)?;
+                }
+                visitor.visit_statement(body)?;
+            }
+            Statement::ForIn {
+                each: _,
+                variable,
+                object,
+                body,
+            } => {
+                if let ForInVariable::Target(target) = variable {
+                    visitor.visit_expression(target)?;
+                }
+                visitor.visit_expression(object)?;
+                visitor.visit_statement(body)?;
+            }
+            Statement::Switch {
+                discriminant,
+                cases,
+            } => {
+                visitor.visit_expression(discriminant)?;
+                for case in cases {
+                    if let Some(value) = &case.value {
+                        visitor.visit_expression(value)?;
+                    }
+                    for v in &case.body {
+                        visitor.visit_statement(v)?;
+                    }
+                }
+            }
+            Statement::Labeled { label: _, body } => visitor.visit_statement(body)?,
+            Statement::Try {
+                body,
+                catches,
+                finally,
+            } => {
+                for v in body {
+                    visitor.visit_statement(v)?;
+                }
+                for catch in catches {
+                    for v in &catch.body {
+                        visitor.visit_statement(v)?;
+                    }
+                }
+                for v in finally.iter().flatten() {
+                    visitor.visit_statement(v)?;
+                }
+            }
+            Statement::Throw(v) => visitor.visit_expression(v)?,
+            Statement::Return(v) => {
+                if let Some(v) = v {
+                    visitor.visit_expression(v)?;
+                }
+            }
+            Statement::Break(_) | Statement::Continue(_) => {}
+            Statement::Expression(v) => visitor.visit_expression(v)?,
+            Statement::Function(function) => {
+                for v in &function.block {
+                    visitor.visit_statement(v)?;
+                }
+            }
         }
 
         Ok(())
@@ -217,26 +674,16 @@ pub mod visitor {
 }
 
 /// Abstract folder.
-mod fold {
+pub mod fold {
     use super::*;
 
-    pub trait Folder {
-        fn fold_expression(&mut self, v: Box<Expression>) -> Box<Expression> {
-            v
+    pub trait Folder: Sized {
+        fn fold_expression(&mut self, v: Expression) -> Expression {
+            walk_expression(self, v)
         }
 
-        fn fold_statement(&mut self, v: Box<Statement>) -> Box<Statement> {
-            match *v {
-                Statement::Variable {
-                    name,
-                    var_type,
-                    value,
-                } => Box::new(Statement::Variable {
-                    name,
-                    var_type,
-                    value: self.fold_expression(value),
-                }),
-            }
+        fn fold_statement(&mut self, v: Statement) -> Statement {
+            walk_statement(self, v)
         }
 
         fn fold_function(&mut self, v: Function) -> Function {
@@ -246,6 +693,7 @@ mod fold {
                 arguments,
                 return_type,
                 block,
+                condition,
             } = v;
 
             let block = block
@@ -261,6 +709,7 @@ mod fold {
                 arguments,
                 return_type,
                 block,
+                condition,
             }
         }
 
@@ -300,4 +749,227 @@ mod fold {
             Package { name, classes }
         }
     }
+
+    /// Folds the sub-expressions of an expression.
+    pub fn walk_expression<F: Folder>(folder: &mut F, v: Expression) -> Expression {
+        match v {
+            Expression::BinaryOperation { lhs, operator, rhs } => Expression::BinaryOperation {
+                lhs: Box::new(folder.fold_expression(*lhs)),
+                operator,
+                rhs: Box::new(folder.fold_expression(*rhs)),
+            },
+            Expression::UnaryOperation { operator, value } => Expression::UnaryOperation {
+                operator,
+                value: Box::new(folder.fold_expression(*value)),
+            },
+            Expression::Array(elements) => Expression::Array(
+                elements
+                    .into_iter()
+                    .map(|element| {
+                        element.map(|element| Box::new(folder.fold_expression(*element)))
+                    })
+                    .collect(),
+            ),
+            Expression::Object(properties) => Expression::Object(
+                properties
+                    .into_iter()
+                    .map(|(name, value)| (name, Box::new(folder.fold_expression(*value))))
+                    .collect(),
+            ),
+            Expression::Property { object, name } => Expression::Property {
+                object: Box::new(folder.fold_expression(*object)),
+                name,
+            },
+            Expression::Index { object, index } => Expression::Index {
+                object: Box::new(folder.fold_expression(*object)),
+                index: Box::new(folder.fold_expression(*index)),
+            },
+            Expression::Function(function) => {
+                Expression::Function(fold_local_function(folder, *function))
+            }
+            Expression::Call {
+                function,
+                arguments,
+            } => Expression::Call {
+                function: Box::new(folder.fold_expression(*function)),
+                arguments: arguments
+                    .into_iter()
+                    .map(|argument| folder.fold_expression(argument))
+                    .collect(),
+            },
+            Expression::New { class, arguments } => Expression::New {
+                class: Box::new(folder.fold_expression(*class)),
+                arguments: arguments
+                    .into_iter()
+                    .map(|argument| folder.fold_expression(argument))
+                    .collect(),
+            },
+            Expression::Ternary {
+                condition,
+                if_true,
+                if_false,
+            } => Expression::Ternary {
+                condition: Box::new(folder.fold_expression(*condition)),
+                if_true: Box::new(folder.fold_expression(*if_true)),
+                if_false: Box::new(folder.fold_expression(*if_false)),
+            },
+            Expression::Update {
+                operator,
+                prefix,
+                target,
+            } => Expression::Update {
+                operator,
+                prefix,
+                target: Box::new(folder.fold_expression(*target)),
+            },
+            Expression::Assignment {
+                target,
+                operator,
+                value,
+            } => Expression::Assignment {
+                target: Box::new(folder.fold_expression(*target)),
+                operator,
+                value: Box::new(folder.fold_expression(*value)),
+            },
+            x => x,
+        }
+    }
+
+    /// Folds the statements of a function defined inside another function.
+    fn fold_local_function<F: Folder>(folder: &mut F, v: LocalFunction) -> Box<LocalFunction> {
+        Box::new(LocalFunction {
+            block: v
+                .block
+                .into_iter()
+                .map(|v| folder.fold_statement(v))
+                .collect(),
+            ..v
+        })
+    }
+
+    /// Folds the expressions and nested statements of a statement.
+    pub fn walk_statement<F: Folder>(folder: &mut F, v: Statement) -> Statement {
+        match v {
+            Statement::Variable {
+                name,
+                var_type,
+                value,
+            } => Statement::Variable {
+                name,
+                var_type,
+                value: Box::new(folder.fold_expression(*value)),
+            },
+            Statement::Block(block) => Statement::Block(
+                block
+                    .into_iter()
+                    .map(|v| folder.fold_statement(v))
+                    .collect(),
+            ),
+            Statement::Config { condition, block } => Statement::Config {
+                condition,
+                block: block
+                    .into_iter()
+                    .map(|v| folder.fold_statement(v))
+                    .collect(),
+            },
+            Statement::If {
+                condition,
+                body,
+                else_body,
+            } => Statement::If {
+                condition: Box::new(folder.fold_expression(*condition)),
+                body: Box::new(folder.fold_statement(*body)),
+                else_body: else_body.map(|v| Box::new(folder.fold_statement(*v))),
+            },
+            Statement::While { condition, body } => Statement::While {
+                condition: Box::new(folder.fold_expression(*condition)),
+                body: Box::new(folder.fold_statement(*body)),
+            },
+            Statement::DoWhile { body, condition } => Statement::DoWhile {
+                body: Box::new(folder.fold_statement(*body)),
+                condition: Box::new(folder.fold_expression(*condition)),
+            },
+            Statement::For {
+                init,
+                condition,
+                update,
+                body,
+            } => Statement::For {
+                init: init.map(|v| Box::new(folder.fold_statement(*v))),
+                condition: condition.map(|v| Box::new(folder.fold_expression(*v))),
+                update: update.map(|v| Box::new(folder.fold_expression(*v))),
+                body: Box::new(folder.fold_statement(*body)),
+            },
+            Statement::ForIn {
+                each,
+                variable,
+                object,
+                body,
+            } => Statement::ForIn {
+                each,
+                variable: match variable {
+                    ForInVariable::Target(target) => {
+                        ForInVariable::Target(Box::new(folder.fold_expression(*target)))
+                    }
+                    declaration => declaration,
+                },
+                object: Box::new(folder.fold_expression(*object)),
+                body: Box::new(folder.fold_statement(*body)),
+            },
+            Statement::Switch {
+                discriminant,
+                cases,
+            } => Statement::Switch {
+                discriminant: Box::new(folder.fold_expression(*discriminant)),
+                cases: cases
+                    .into_iter()
+                    .map(|case| SwitchCase {
+                        value: case.value.map(|v| Box::new(folder.fold_expression(*v))),
+                        body: case
+                            .body
+                            .into_iter()
+                            .map(|v| folder.fold_statement(v))
+                            .collect(),
+                    })
+                    .collect(),
+            },
+            Statement::Labeled { label, body } => Statement::Labeled {
+                label,
+                body: Box::new(folder.fold_statement(*body)),
+            },
+            Statement::Try {
+                body,
+                catches,
+                finally,
+            } => Statement::Try {
+                body: body.into_iter().map(|v| folder.fold_statement(v)).collect(),
+                catches: catches
+                    .into_iter()
+                    .map(|catch| CatchClause {
+                        body: catch
+                            .body
+                            .into_iter()
+                            .map(|v| folder.fold_statement(v))
+                            .collect(),
+                        ..catch
+                    })
+                    .collect(),
+                finally: finally.map(|block| {
+                    block
+                        .into_iter()
+                        .map(|v| folder.fold_statement(v))
+                        .collect()
+                }),
+            },
+            Statement::Throw(v) => Statement::Throw(Box::new(folder.fold_expression(*v))),
+            Statement::Return(v) => {
+                Statement::Return(v.map(|v| Box::new(folder.fold_expression(*v))))
+            }
+            Statement::Expression(v) => Statement::Expression(Box::new(folder.fold_expression(*v))),
+            Statement::Function(function) => {
+                Statement::Function(fold_local_function(folder, *function))
+            }
+            x @ (Statement::Break(_) | Statement::Continue(_)) => x,
+        }
+    }
 }
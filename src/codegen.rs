@@ -1,37 +1,110 @@
 use crate::ast::Visitor;
-use swf::avm2::types::Op;
+use swf::avm2::types::{Index, Multiname, Op};
 
 pub mod context;
 
 mod function;
+mod resolver;
 
 /// A simple label index.
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub struct LabelIndex(u32);
 
 /// A wrapped Opcode.
 ///
 /// This will be converted to normal opcodes later when the code is passed through a label resolver.
-#[derive(Debug)]
+#[derive(Debug, PartialEq)]
 pub enum CompilerOp {
     /// These opcodes are written as is, and will not be transformed.
     Raw(Op),
     Label(LabelIndex),
     IfFalse(LabelIndex),
     IfTrue(LabelIndex),
+    IfEq(LabelIndex),
+    IfNe(LabelIndex),
+    IfStrictEq(LabelIndex),
+    IfStrictNe(LabelIndex),
+    IfLt(LabelIndex),
+    IfLe(LabelIndex),
+    IfGt(LabelIndex),
+    IfGe(LabelIndex),
+    IfNlt(LabelIndex),
+    IfNle(LabelIndex),
+    IfNgt(LabelIndex),
+    IfNge(LabelIndex),
+    Jump(LabelIndex),
+    /// Jumps to the case at the index on top of the stack, or to `default` if the index is out of range.
+    LookupSwitch {
+        default: LabelIndex,
+        cases: Vec<LabelIndex>,
+    },
+}
+
+impl CompilerOp {
+    /// Returns the labels this operation can jump to.
+    pub fn targets(&self) -> Vec<LabelIndex> {
+        match self {
+            Self::IfFalse(label)
+            | Self::IfTrue(label)
+            | Self::IfEq(label)
+            | Self::IfNe(label)
+            | Self::IfStrictEq(label)
+            | Self::IfStrictNe(label)
+            | Self::IfLt(label)
+            | Self::IfLe(label)
+            | Self::IfGt(label)
+            | Self::IfGe(label)
+            | Self::IfNlt(label)
+            | Self::IfNle(label)
+            | Self::IfNgt(label)
+            | Self::IfNge(label)
+            | Self::Jump(label) => vec![*label],
+            Self::LookupSwitch { default, cases } => std::iter::once(*default)
+                .chain(cases.iter().copied())
+                .collect(),
+            Self::Raw(_) | Self::Label(_) => Vec::new(),
+        }
+    }
+}
+
+/// An exception handler, with its offsets given as labels until they are resolved.
+#[derive(Debug, PartialEq)]
+pub struct CompilerException {
+    /// Start of the protected code.
+    pub from: LabelIndex,
+    /// End of the protected code, exclusive.
+    pub to: LabelIndex,
+    /// Start of the handler.
+    pub target: LabelIndex,
+    pub variable_name: Index<Multiname>,
+    /// The type of exceptions that are handled, zero handles everything.
+    pub type_name: Index<Multiname>,
 }
 
 /// A visitor to generate ABC bytecode from a AST.
-#[derive(Debug)]
+#[derive(Debug, Default)]
 pub struct CodeGenerator {
     context: context::ConstantPoolContext,
 }
 
-impl Default for CodeGenerator {
-    fn default() -> Self {
-        Self {
-            context: Default::default(),
+impl CodeGenerator {
+    /// Generates a function, a method also knows about the members of its class.
+    fn generate_function<'ast>(
+        &mut self,
+        v: &'ast crate::ast::Function,
+        class: Option<&'ast crate::ast::Class>,
+    ) -> Result<(), ()> {
+        let mut var_visitor = function::LocalResolverVisitor::default();
+        var_visitor.visit_function(v)?;
+
+        let mut function_gen = function::FunctionGenerator::new(var_visitor, &mut self.context);
+        if let Some(class) = class {
+            // The constructor is the method named after its class.
+            function_gen = function_gen.with_class(class, v.name == class.name);
         }
+        function_gen.visit_function(v)?;
+
+        Ok(())
     }
 }
 
@@ -39,19 +112,18 @@ impl<'ast> Visitor<'ast> for CodeGenerator {
     type Error = ();
 
     fn visit_function(&mut self, v: &'ast crate::ast::Function) -> Result<(), Self::Error> {
-        let mut var_visitor = function::LocalResolverVisitor::default();
-        var_visitor.visit_function(v)?;
+        self.generate_function(v, None)
+    }
 
-        let mut function_gen = function::FunctionGenerator::new(var_visitor, &mut self.context);
-        function_gen.visit_function(v)?;
+    fn visit_class(&mut self, v: &'ast crate::ast::Class) -> Result<(), Self::Error> {
+        // FIXME: Generate the class itself, for now only its methods are generated.
+        for function in &v.functions {
+            self.generate_function(function, Some(v))?;
+        }
 
         Ok(())
     }
 
-    fn visit_class(&mut self, _v: &'ast crate::ast::Class) -> Result<(), Self::Error> {
-        todo!()
-    }
-
     fn visit_package(&mut self, _v: &'ast crate::ast::Package) -> Result<(), Self::Error> {
         todo!()
     }
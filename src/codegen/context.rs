@@ -3,7 +3,9 @@ use std::{
     collections::HashMap,
     ops::{Deref, DerefMut},
 };
-use swf::avm2::types::{ConstantPool, Index};
+use swf::avm2::types::{
+    ConstantPool, Index, Method, MethodBody, Multiname, Namespace, NamespaceSet,
+};
 
 /// A context that is used when generating code for a function.
 #[derive(Debug)]
@@ -13,7 +15,15 @@ pub struct FunctionGenContext<'a> {
     labels: HashMap<String, LabelIndex>,
 }
 
-impl FunctionGenContext<'_> {
+impl<'a> FunctionGenContext<'a> {
+    pub fn new(const_pool: &'a mut ConstantPoolContext) -> Self {
+        Self {
+            const_pool,
+            label_counter: 0,
+            labels: HashMap::new(),
+        }
+    }
+
     pub fn get_next_label(&mut self) -> LabelIndex {
         let index = LabelIndex(self.label_counter);
         self.label_counter += 1;
@@ -45,27 +55,125 @@ impl<'a> DerefMut for FunctionGenContext<'a> {
     }
 }
 
-/// A context that is used when a constant pool is required.
+/// A context that is used when a constant pool is required, it also collects the generated methods.
 #[derive(Debug)]
 pub struct ConstantPoolContext {
     const_pool: ConstantPool,
+    methods: Vec<Method>,
+    method_bodies: Vec<MethodBody>,
 }
 
 impl ConstantPoolContext {
     /// Adds a string into constant pool (unless it already exists) and a index into the pool will be returned.
     pub fn add_string(&mut self, val: &String) -> Index<String> {
-        let index = if let Some(index) = self.const_pool.strings.iter().position(|x| x == val) {
-            index
-        } else {
-            // Create a index.
-            let index = self.const_pool.strings.len();
-            // Push the value.
-            self.const_pool.strings.push(val.clone());
-            index
-        } as u32;
+        Index::new(intern(&mut self.const_pool.strings, val, |x, y| x == y))
+    }
+
+    /// Adds a int into constant pool (unless it already exists) and a index into the pool will be returned.
+    pub fn add_int(&mut self, val: i32) -> Index<i32> {
+        Index::new(intern(&mut self.const_pool.ints, &val, |x, y| x == y))
+    }
+
+    /// Adds a uint into constant pool (unless it already exists) and a index into the pool will be returned.
+    pub fn add_uint(&mut self, val: u32) -> Index<u32> {
+        Index::new(intern(&mut self.const_pool.uints, &val, |x, y| x == y))
+    }
+
+    /// Adds a double into constant pool (unless it already exists) and a index into the pool will be returned.
+    pub fn add_double(&mut self, val: f64) -> Index<f64> {
+        // Compare bit patterns, so that 0.0 and -0.0 stay distinct entries.
+        Index::new(intern(&mut self.const_pool.doubles, &val, |x, y| {
+            x.to_bits() == y.to_bits()
+        }))
+    }
+
+    /// Adds a namespace into constant pool (unless it already exists) and a index into the pool will be returned.
+    pub fn add_namespace(&mut self, val: Namespace) -> Index<Namespace> {
+        Index::new(intern(&mut self.const_pool.namespaces, &val, |x, y| x == y))
+    }
+
+    /// Adds a namespace set into constant pool (unless it already exists) and a index into the pool will be returned.
+    pub fn add_namespace_set(&mut self, val: NamespaceSet) -> Index<NamespaceSet> {
+        Index::new(intern(&mut self.const_pool.namespace_sets, &val, |x, y| {
+            x == y
+        }))
+    }
+
+    /// Adds a multiname into constant pool (unless it already exists) and a index into the pool will be returned.
+    pub fn add_multiname(&mut self, val: Multiname) -> Index<Multiname> {
+        Index::new(intern(&mut self.const_pool.multinames, &val, |x, y| x == y))
+    }
+
+    /// Adds a QName in the public (empty package) namespace, this is how top level classes like `RegExp` are referenced.
+    pub fn add_public_qname(&mut self, name: &String) -> Index<Multiname> {
+        self.add_package_qname(&String::new(), name)
+    }
 
+    /// Adds a QName of a public definition in a package, e.g. `flash.display` and `Sprite`.
+    pub fn add_package_qname(&mut self, package: &String, name: &String) -> Index<Multiname> {
+        let package = self.add_string(package);
+        let namespace = self.add_namespace(Namespace::Package(package));
+        let name = self.add_string(name);
+
+        self.add_multiname(Multiname::QName { namespace, name })
+    }
+
+    /// Adds a runtime multiname that looks up a name from the stack in the public namespace, used for computed
+    /// property names such as `a[i]`.
+    pub fn add_public_multiname_l(&mut self) -> Index<Multiname> {
+        let package = self.add_string(&String::new());
+        let namespace = self.add_namespace(Namespace::Package(package));
+        let namespace_set = self.add_namespace_set(vec![namespace]);
+
+        self.add_multiname(Multiname::MultinameL { namespace_set })
+    }
+
+    /// Adds a runtime qualified name, which takes its namespace from the stack, used for names like `ns::name`.
+    pub fn add_rtqname(&mut self, name: &String) -> Index<Multiname> {
+        let name = self.add_string(name);
+
+        self.add_multiname(Multiname::RTQName { name })
+    }
+
+    /// Adds a method together with its body, and returns the index of the method.
+    ///
+    /// Unlike constant pool entries methods are never shared, and their indices start at zero.
+    pub fn add_method(&mut self, mut method: Method, mut body: MethodBody) -> Index<Method> {
+        let index = self.methods.len() as u32;
+        method.body = Some(Index::new(self.method_bodies.len() as u32));
+        body.method = Index::new(index);
+
+        self.methods.push(method);
+        self.method_bodies.push(body);
         Index::new(index)
     }
+
+    /// Returns the bodies of the methods added so far, in the order they were added.
+    pub fn method_bodies(&self) -> &[MethodBody] {
+        &self.method_bodies
+    }
+
+    /// Returns the constant pool built so far.
+    pub fn constant_pool(&self) -> &ConstantPool {
+        &self.const_pool
+    }
+}
+
+/// Finds a value in a constant pool, appending it if it does not exist yet.
+///
+/// Index zero is reserved by the ABC format in every constant pool, so the first entry is at index one.
+fn intern<T: Clone>(pool: &mut Vec<T>, val: &T, eq: impl Fn(&T, &T) -> bool) -> u32 {
+    let index = if let Some(index) = pool.iter().position(|x| eq(x, val)) {
+        index
+    } else {
+        // Create a index.
+        let index = pool.len();
+        // Push the value.
+        pool.push(val.clone());
+        index
+    };
+
+    index as u32 + 1
 }
 
 impl Default for ConstantPoolContext {
@@ -80,6 +188,8 @@ impl Default for ConstantPoolContext {
                 namespace_sets: Vec::new(),
                 multinames: Vec::new(),
             },
+            methods: Vec::new(),
+            method_bodies: Vec::new(),
         }
     }
 }
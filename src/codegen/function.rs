@@ -1,30 +1,60 @@
 //! The purpose of this module is to generate ABC bytecode for functions.
 
-use swf::avm2::types::{Index, MethodBody, Op};
+use swf::avm2::types::{
+    Index, Method, MethodBody, MethodFlags, MethodParam, Multiname, Op, Trait, TraitKind,
+};
 
 use crate::ast::{
-    visitor::{walk_function, walk_statement},
-    Expression, Operator, Statement, Visitor,
+    visitor::{walk_expression, walk_statement},
+    Argument, CatchClause, Class, Expression, ForInVariable, LocalFunction, Operator,
+    QualifiedName, Statement, UnaryOperator, UpdateOperator, Visitor,
+};
+use crate::codegen::{
+    context::{ConstantPoolContext, FunctionGenContext},
+    resolver, CompilerException, CompilerOp, LabelIndex,
 };
-use crate::codegen::context::ConstantPoolContext;
 use crate::parser::common::Type;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
+
+/// The hidden registers that a for-in loop keeps its state in.
+#[derive(Debug, Clone, Copy)]
+struct ForInRegisters {
+    object: u32,
+    index: u32,
+}
+
+/// The hidden registers of a try statement with a finally block.
+#[derive(Debug, Clone, Copy)]
+struct FinallyRegisters {
+    /// The exception that is rethrown once the finally block is done.
+    exception: u32,
+    /// Which exit the finally block continues with once it is done.
+    index: u32,
+}
 
 /// A visitor used to collect and assign indices to all variables/arguments referenced in a function.
-#[derive(Debug)]
+#[derive(Debug, Default)]
 pub struct LocalResolverVisitor<'a> {
     current_index: u32,
-    // FIXME: Maybe store type here too since it might be needed later?
     variables: HashMap<&'a String, u32>,
-}
-
-impl Default for LocalResolverVisitor<'_> {
-    fn default() -> Self {
-        Self {
-            current_index: 0,
-            variables: HashMap::new(),
-        }
-    }
+    /// Declared types of variables, the first declaration of a variable decides its type.
+    types: HashMap<&'a String, &'a Type>,
+    /// Registers of every for-in loop, keyed by the address of the statement.
+    for_in_registers: HashMap<*const Statement, ForInRegisters>,
+    /// Registers holding the value a switch statement compares against, keyed by the address of the statement.
+    switch_registers: HashMap<*const Statement, u32>,
+    /// Registers of every try statement with a finally block, keyed by the address of the statement.
+    finally_registers: HashMap<*const Statement, FinallyRegisters>,
+    /// Holds the value of a `return` while finally blocks run, only allocated if there are any.
+    return_register: Option<u32>,
+    /// Names that nested functions refer to, variables with these names live in the activation object.
+    captured: HashSet<&'a String>,
+    /// Slots of the variables in the activation object.
+    slots: HashMap<&'a String, u32>,
+    /// Holds the activation object, only allocated if any variable is captured.
+    activation_register: Option<u32>,
+    /// Nested function declarations, which are created as soon as the function starts.
+    functions: Vec<&'a LocalFunction>,
 }
 
 impl<'a> LocalResolverVisitor<'a> {
@@ -35,11 +65,64 @@ impl<'a> LocalResolverVisitor<'a> {
     }
 
     /// Adds a variable to contained variables.
-    fn add_var(&mut self, name: &'a String) {
-        if !self.variables.contains_key(name) {
+    fn add_var(&mut self, name: &'a String, ty: &'a Type) {
+        if self.variables.contains_key(name) || self.slots.contains_key(name) {
+            return;
+        }
+
+        if self.captured.contains(name) {
+            self.add_slot(name);
+        } else {
             let index = self.fetch_index();
             self.variables.insert(name, index);
         }
+        self.types.insert(name, ty);
+    }
+
+    /// Gives a captured variable a slot in the activation object.
+    fn add_slot(&mut self, name: &'a String) {
+        let slot_id = self.slots.len() as u32 + 1;
+        self.slots.insert(name, slot_id);
+    }
+
+    /// Resolves the arguments and variables of a function, nested functions are resolved on their own.
+    fn visit_body(&mut self, arguments: &'a [Argument], block: &'a [Statement]) -> Result<(), ()> {
+        let mut captures = CaptureVisitor::default();
+        for statement in block {
+            captures.visit_statement(statement)?;
+        }
+        self.captured = captures.names;
+
+        // NOTE: First argument is always self/this argument, but index 0 is never given out so this is fine.
+
+        // FIXME: What if "arguments" is referenced and stuff like that?
+        //        Also variadic arguments.
+        // Arguments are always passed in registers, captured ones are copied into their slots when the function
+        // starts.
+        for arg in arguments {
+            let index = self.fetch_index();
+            if self.captured.contains(&arg.name) {
+                self.add_slot(&arg.name);
+            } else {
+                self.variables.insert(&arg.name, index);
+            }
+            self.types.insert(&arg.name, &arg.value_type);
+        }
+
+        for statement in block {
+            self.visit_statement(statement)?;
+        }
+
+        if !self.slots.is_empty() {
+            self.activation_register = Some(self.fetch_index());
+        }
+
+        Ok(())
+    }
+
+    /// Resolves a function defined inside another function.
+    pub fn visit_local_function(&mut self, v: &'a LocalFunction) -> Result<(), ()> {
+        self.visit_body(&v.arguments, &v.block)
     }
 }
 
@@ -50,26 +133,75 @@ impl<'ast> Visitor<'ast> for LocalResolverVisitor<'ast> {
         match v {
             Statement::Variable {
                 name,
-                var_type: _,
+                var_type,
                 value: _,
-            } => self.add_var(&name),
+            } => self.add_var(name, var_type),
+            Statement::ForIn { variable, .. } => {
+                // A bare variable is only assigned to, it has to be declared elsewhere.
+                if let ForInVariable::Declaration { name, var_type } = variable {
+                    self.add_var(name, var_type);
+                }
+
+                // These registers have no name, so they can't clash with any variable.
+                let registers = ForInRegisters {
+                    object: self.fetch_index(),
+                    index: self.fetch_index(),
+                };
+                self.for_in_registers.insert(v, registers);
+            }
+            Statement::Switch { .. } => {
+                let register = self.fetch_index();
+                self.switch_registers.insert(v, register);
+            }
+            Statement::Try {
+                body: _,
+                catches,
+                finally,
+            } => {
+                for catch in catches {
+                    self.add_var(&catch.variable, &catch.var_type);
+                }
+
+                if finally.is_some() {
+                    let registers = FinallyRegisters {
+                        exception: self.fetch_index(),
+                        index: self.fetch_index(),
+                    };
+                    self.finally_registers.insert(v, registers);
+
+                    if self.return_register.is_none() {
+                        self.return_register = Some(self.fetch_index());
+                    }
+                }
+            }
+            Statement::Function(function) => {
+                let name = function
+                    .name
+                    .as_ref()
+                    .expect("Function declarations should have a name");
+                self.add_var(name, &Type::Any);
+                self.functions.push(function);
+
+                // The variables of the nested function are its own.
+                return Ok(());
+            }
+            // Nested statements are handled by walk_statement.
+            _ => {}
         }
 
         walk_statement(self, v)
     }
 
-    fn visit_function(&mut self, v: &'ast crate::ast::Function) -> Result<(), Self::Error> {
-        // NOTE: First argument is always self/this argument, but index 0 is never given out so this is fine.
-
-        // FIXME: What if "arguments" is referenced and stuff like that?
-        //        Also variadic arguments.
-        // Some locals are reserved for arguments.
-        for arg in &v.arguments {
-            self.add_var(&arg.name)
+    fn visit_expression(&mut self, v: &'ast Expression) -> Result<(), Self::Error> {
+        match v {
+            // The variables of the nested function are its own.
+            Expression::Function(_) => Ok(()),
+            _ => walk_expression(self, v),
         }
+    }
 
-        // Walk the rest of the function.
-        walk_function(self, v)
+    fn visit_function(&mut self, v: &'ast crate::ast::Function) -> Result<(), Self::Error> {
+        self.visit_body(&v.arguments, &v.block)
     }
 
     fn visit_class(&mut self, _v: &'ast crate::ast::Class) -> Result<(), Self::Error> {
@@ -81,15 +213,127 @@ impl<'ast> Visitor<'ast> for LocalResolverVisitor<'ast> {
     }
 }
 
+/// Collects the names that the nested functions of a function refer to.
+///
+/// Variables with these names have to live in an activation object instead of a register, so that the nested
+/// functions can still reach them through their scope chain. Names a nested function declares itself are collected
+/// as well, which only costs an unneeded slot if the enclosing function has a variable with the same name.
+#[derive(Debug, Default)]
+struct CaptureVisitor<'a> {
+    /// How many nested functions deep the visitor currently is.
+    depth: u32,
+    names: HashSet<&'a String>,
+}
+
+impl<'ast> Visitor<'ast> for CaptureVisitor<'ast> {
+    type Error = ();
+
+    fn visit_statement(&mut self, v: &'ast Statement) -> Result<(), Self::Error> {
+        if let Statement::Function(_) = v {
+            self.depth += 1;
+            walk_statement(self, v)?;
+            self.depth -= 1;
+            return Ok(());
+        }
+
+        walk_statement(self, v)
+    }
+
+    fn visit_expression(&mut self, v: &'ast Expression) -> Result<(), Self::Error> {
+        match v {
+            Expression::Variable(name) if self.depth > 0 => {
+                self.names.insert(name);
+            }
+            Expression::Function(_) => {
+                self.depth += 1;
+                walk_expression(self, v)?;
+                self.depth -= 1;
+                return Ok(());
+            }
+            _ => {}
+        }
+
+        walk_expression(self, v)
+    }
+}
+
+/// A statement that `break` and `continue` can jump out of.
+#[derive(Debug)]
+struct JumpTarget {
+    labels: Vec<String>,
+    break_label: LabelIndex,
+    /// Only loops can be continued.
+    continue_label: Option<LabelIndex>,
+    /// Whether a `break` without a label exits this statement, which is not the case for labeled blocks.
+    unlabeled_break: bool,
+}
+
+/// Where control goes when leaving a statement, once every finally block on the way has run.
+#[derive(Debug, Clone, Copy)]
+enum Exit {
+    /// Jumps to a label of the jump target at `depth` in the jump target stack.
+    Jump { label: LabelIndex, depth: usize },
+    /// Returns from the function, with the value in the return register if `value` is set.
+    Return { value: bool },
+}
+
+/// A try statement with a finally block, whose try block or catch clauses are currently being generated.
+///
+/// Every way out of the try statement goes through the finally block, which is generated only once. The exit that
+/// is taken is stored in a register as an index into `exits`, which the end of the finally block switches on.
+#[derive(Debug)]
+struct FinallyScope {
+    /// Number of jump targets outside of the try statement.
+    jump_depth: usize,
+    finally_label: LabelIndex,
+    index_register: u32,
+    /// Where the finally block continues, the first exit leaves the try statement normally and the second one
+    /// rethrows the exception. The exit is `None` if the label already leads to its destination.
+    exits: Vec<(LabelIndex, Option<Exit>)>,
+}
+
 #[derive(Debug)]
 pub struct FunctionGenerator<'ast, 'a> {
-    context: &'a mut ConstantPoolContext,
+    context: FunctionGenContext<'a>,
     locals: HashMap<&'ast String, u32>,
+    local_types: HashMap<&'ast String, &'ast Type>,
+    for_in_registers: HashMap<*const Statement, ForInRegisters>,
+    switch_registers: HashMap<*const Statement, u32>,
+    finally_registers: HashMap<*const Statement, FinallyRegisters>,
+    return_register: Option<u32>,
+    /// Slots of the variables that nested functions can access.
+    slots: HashMap<&'ast String, u32>,
+    activation_register: Option<u32>,
+    /// Nested function declarations, which are created as soon as the function starts.
+    functions: Vec<&'ast LocalFunction>,
+    /// Number of registers used, including `this` and hidden registers.
+    num_locals: u32,
+    /// Registers for intermediate values that are not in use at the moment.
+    temporaries: Vec<u32>,
     // Max stack objects at once at any time during execution.
     max_stack: u32,
     current_stack: u32,
-    /// Generated code.
-    code: Vec<Op>,
+    /// Depth of the scope stack when the function starts, which holds the scopes of the functions it is nested in.
+    init_scope_depth: u32,
+    /// Objects this function has pushed onto the scope stack, at the moment and the most at any time.
+    scope_depth: u32,
+    max_scope_depth: u32,
+    /// Generated code, labels are resolved once the whole function has been generated.
+    code: Vec<CompilerOp>,
+    /// Statements that are currently being generated and can be exited with `break` or `continue`.
+    jump_targets: Vec<JumpTarget>,
+    /// Labels of a labeled statement that have not been claimed by a jump target yet.
+    pending_labels: Vec<String>,
+    /// Try statements with a finally block that are currently being generated, innermost last.
+    finally_scopes: Vec<FinallyScope>,
+    /// Exception handlers, inner handlers come first since the first matching handler is used.
+    exceptions: Vec<CompilerException>,
+    return_type: Option<&'ast Type>,
+    /// Names from outside of the function: members of its class, including methods, and locals of the functions
+    /// it is nested in.
+    members: HashSet<&'ast String>,
+    /// Constant members of `this` that are written with initproperty, only set while generating a constructor.
+    constants: HashSet<&'ast String>,
 }
 
 impl<'ast, 'a> FunctionGenerator<'ast, 'a> {
@@ -98,166 +342,3118 @@ impl<'ast, 'a> FunctionGenerator<'ast, 'a> {
         context: &'a mut ConstantPoolContext,
     ) -> Self {
         Self {
-            context,
+            context: FunctionGenContext::new(context),
             locals: variables.variables,
+            local_types: variables.types,
+            for_in_registers: variables.for_in_registers,
+            switch_registers: variables.switch_registers,
+            finally_registers: variables.finally_registers,
+            return_register: variables.return_register,
+            slots: variables.slots,
+            activation_register: variables.activation_register,
+            functions: variables.functions,
+            // NOTE: Plus one required since register 0 is the "this" object.
+            num_locals: variables.current_index + 1,
+            temporaries: Vec::new(),
             code: Vec::new(),
             max_stack: 0,
             current_stack: 0,
+            init_scope_depth: 0,
+            scope_depth: 0,
+            max_scope_depth: 0,
+            jump_targets: Vec::new(),
+            pending_labels: Vec::new(),
+            finally_scopes: Vec::new(),
+            exceptions: Vec::new(),
+            return_type: None,
+            members: HashSet::new(),
+            constants: HashSet::new(),
+        }
+    }
+
+    /// Lets the function know about the members of its class. A constructor also initialises the constant members.
+    pub fn with_class(mut self, class: &'ast Class, constructor: bool) -> Self {
+        let variables = class.members.iter().map(|member| &member.name);
+        let methods = class.functions.iter().map(|function| &function.name);
+        self.members = variables.chain(methods).collect();
+
+        if constructor {
+            self.constants = class
+                .members
+                .iter()
+                .filter(|member| member.constant)
+                .map(|member| &member.name)
+                .collect();
         }
+        self
     }
 
     // Meant to be used to emit raw operations, if possible, use wrappers instead.
     fn emit_op(&mut self, op: Op) {
-        self.code.push(op);
+        self.code.push(CompilerOp::Raw(op));
     }
 
-    /// A marker function to calculate a function's max stack value.
-    fn stack_push(&mut self) {
-        self.current_stack += 1;
+    /// Marks the position that branches to `label` jump to.
+    fn emit_label(&mut self, label: LabelIndex) {
+        self.code.push(CompilerOp::Label(label));
+    }
 
-        if self.max_stack < self.current_stack {
-            self.max_stack = self.current_stack
-        }
+    /// Pops a value from the stack and jumps to `label` if it is false.
+    fn if_false(&mut self, label: LabelIndex) {
+        self.code.push(CompilerOp::IfFalse(label));
+        self.stack_pop();
     }
 
-    /// A marker function to calculate a function's max stack value.
-    fn stack_pop(&mut self) {
-        self.current_stack -= 1;
+    /// Pops a value from the stack and jumps to `label` if it is true.
+    fn if_true(&mut self, label: LabelIndex) {
+        self.code.push(CompilerOp::IfTrue(label));
+        self.stack_pop();
     }
 
-    fn push_int(&mut self, val: i32) {
-        if let Ok(value) = val.try_into() {
-            self.emit_op(Op::PushByte { value })
-        } else if let Ok(value) = val.try_into() {
-            self.emit_op(Op::PushShort { value })
-        } else {
-            todo!("Unhandled");
-        }
+    /// Pops two values from the stack and jumps to `label` if they are not strictly equal.
+    fn if_strict_ne(&mut self, label: LabelIndex) {
+        self.code.push(CompilerOp::IfStrictNe(label));
+        self.stack_pop();
+        self.stack_pop();
+    }
 
-        self.stack_push()
+    /// Unconditionally jumps to `label`.
+    fn jump(&mut self, label: LabelIndex) {
+        self.code.push(CompilerOp::Jump(label));
     }
 
-    fn push_string(&mut self, val: &String) {
-        let index = self.context.add_string(val);
-        self.emit_op(Op::PushString { value: index });
-        self.stack_push()
+    /// Duplicates the value on top of the stack.
+    fn dup(&mut self) {
+        self.emit_op(Op::Dup);
+        self.stack_push();
     }
 
-    fn push_bool(&mut self, val: bool) {
-        if val {
-            self.emit_op(Op::PushTrue)
-        } else {
-            self.emit_op(Op::PushFalse)
-        }
-        self.stack_push()
+    /// Discards the value on top of the stack.
+    fn pop(&mut self) {
+        self.emit_op(Op::Pop);
+        self.stack_pop();
     }
 
-    // Gets a value in a local register and pushes it onto the stack.
-    fn get_local(&mut self, name: &String) {
-        self.emit_op(Op::GetLocal {
-            // FIXME: return err, instead of unwrap.
-            index: *self.locals.get(name).unwrap(),
+    /// Enters a statement that can be exited with `break`, labels directly in front of it refer to it.
+    fn push_jump_target(
+        &mut self,
+        break_label: LabelIndex,
+        continue_label: Option<LabelIndex>,
+        unlabeled_break: bool,
+    ) {
+        self.jump_targets.push(JumpTarget {
+            labels: std::mem::take(&mut self.pending_labels),
+            break_label,
+            continue_label,
+            unlabeled_break,
         });
-        self.stack_push()
     }
 
-    /// Pops a value from the stack and sets it to the specified local register.
-    fn set_local(&mut self, name: &String) {
-        self.emit_op(Op::SetLocal {
-            index: *self.locals.get(name).unwrap(),
-        });
-        self.stack_pop();
+    fn pop_jump_target(&mut self) {
+        self.jump_targets.pop();
     }
-}
 
-impl<'ast, 'a> Visitor<'ast> for FunctionGenerator<'ast, 'a> {
-    type Error = ();
+    /// Finds the index of the statement that a `break` or `continue` refers to, the innermost one if there is
+    /// no label.
+    fn find_jump_target(&self, label: Option<&String>, is_continue: bool) -> Option<usize> {
+        self.jump_targets.iter().rposition(|target| {
+            if is_continue && target.continue_label.is_none() {
+                return false;
+            }
 
-    fn visit_expression(&mut self, v: &'ast crate::ast::Expression) -> Result<(), Self::Error> {
+            match label {
+                Some(label) => target.labels.contains(label),
+                None => target.unlabeled_break,
+            }
+        })
+    }
+
+    /// Generates a loop body, which `break` and `continue` jump out of.
+    fn visit_loop_body(
+        &mut self,
+        body: &'ast Statement,
+        break_label: LabelIndex,
+        continue_label: LabelIndex,
+    ) -> Result<(), ()> {
+        self.push_jump_target(break_label, Some(continue_label), true);
+        let result = self.visit_statement(body);
+        self.pop_jump_target();
+        result
+    }
+
+    /// Evaluates an expression only for its side effects, so that no value has to be computed for some of them.
+    fn visit_discarded(&mut self, v: &'ast Expression) -> Result<(), ()> {
         match v {
-            Expression::BinaryOperation { lhs, operator, rhs } => {
-                self.visit_expression(&lhs)?;
-                self.visit_expression(&rhs)?;
-
-                match operator {
-                    Operator::Add => self.emit_op(Op::Add),
-                    Operator::Sub => self.emit_op(Op::Subtract),
-                    Operator::Mul => self.emit_op(Op::Multiply),
-                    Operator::Div => self.emit_op(Op::Divide),
+            Expression::Update {
+                operator,
+                prefix,
+                target,
+            } => self.visit_update(operator, *prefix, target, false),
+            Expression::Assignment {
+                target,
+                operator,
+                value,
+            } => self.visit_assignment(target, operator.as_ref(), value, false),
+            Expression::Call {
+                function,
+                arguments,
+            } => self.visit_call(function, arguments, false),
+            _ => {
+                self.visit_expression(v)?;
+                self.pop();
+                Ok(())
+            }
+        }
+    }
+
+    /// Generates an increment or decrement, the result is only left on the stack if `keep_value` is set.
+    fn visit_update(
+        &mut self,
+        operator: &UpdateOperator,
+        prefix: bool,
+        target: &'ast Expression,
+        keep_value: bool,
+    ) -> Result<(), ()> {
+        let ty = self.target_type(target);
+        let is_int = ty == Some(&Type::Int);
+
+        // Without a result a register can be updated in place, as long as the result has the right type.
+        let register = match target {
+            Expression::Variable(name) => self.locals.get(name).copied(),
+            _ => None,
+        };
+        if let (false, Some(index), Some(Type::Int | Type::Number | Type::Any)) =
+            (keep_value, register, ty)
+        {
+            self.emit_op(match (operator, is_int) {
+                (UpdateOperator::Increment, true) => Op::IncLocalI { index },
+                (UpdateOperator::Increment, false) => Op::IncLocal { index },
+                (UpdateOperator::Decrement, true) => Op::DecLocalI { index },
+                (UpdateOperator::Decrement, false) => Op::DecLocal { index },
+            });
+            return Ok(());
+        }
+
+        let operation = match (operator, is_int) {
+            (UpdateOperator::Increment, true) => Op::IncrementI,
+            (UpdateOperator::Increment, false) => Op::Increment,
+            (UpdateOperator::Decrement, true) => Op::DecrementI,
+            (UpdateOperator::Decrement, false) => Op::Decrement,
+        };
+
+        // The result of a postfix operator is the old value, converted to a number. A local can leave it below
+        // the new value, for properties the object has to be there instead, so it's kept in a register.
+        let keep_old_value = !prefix && keep_value;
+        let old_value = (keep_old_value && ty.is_none()).then(|| self.get_temporary());
+
+        self.visit_store(target, true, prefix && keep_value, move |this| {
+            if keep_old_value {
+                if !matches!(ty, Some(Type::Int | Type::Number)) {
+                    this.emit_op(Op::ConvertD);
                 }
+                this.dup();
+                if let Some(index) = old_value {
+                    this.set_register(index);
+                }
+            }
 
-                // These operations pop two values off the stack and pushes one back. So we pop only once.
-                self.stack_pop();
+            this.emit_op(operation);
+            if let Some(ty) = ty.filter(|ty| !matches!(ty, Type::Int | Type::Number | Type::Any)) {
+                this.coerce(ty)?;
             }
-            Expression::Integer(val) => self.push_int(*val),
-            Expression::String(val) => self.push_string(val),
-            Expression::Bool(x) => self.push_bool(*x),
-            Expression::Variable(name) => self.get_local(name),
+
+            Ok(())
+        })?;
+
+        if let Some(index) = old_value {
+            self.get_register(index);
+            self.free_temporary(index);
         }
 
         Ok(())
     }
 
-    fn visit_statement(&mut self, v: &'ast crate::ast::Statement) -> Result<(), Self::Error> {
-        match v {
-            Statement::Variable {
-                name,
-                var_type,
-                value,
-            } => {
-                // Emit expression code.
-                self.visit_expression(&value)?;
+    /// Generates an assignment, the assigned value is only left on the stack if `keep_value` is set.
+    fn visit_assignment(
+        &mut self,
+        target: &'ast Expression,
+        operator: Option<&Operator>,
+        value: &'ast Expression,
+        keep_value: bool,
+    ) -> Result<(), ()> {
+        let ty = self.target_type(target);
 
-                // Emit a coerce operation.
-                match var_type {
-                    Type::Any => self.emit_op(Op::CoerceA),
-                    _ => todo!(),
+        self.visit_store(target, operator.is_some(), keep_value, |this| {
+            match operator {
+                Some(operator) => this.visit_operator(operator, value)?,
+                None => this.visit_expression(value)?,
+            }
+            if let Some(ty) = ty {
+                this.coerce(ty)?;
+            }
+
+            Ok(())
+        })
+    }
+
+    /// Stores a value into the target of an assignment or increment. `compute` generates the value, with the old
+    /// value on top of the stack if `load` is set. The stored value is only left on the stack if `keep_value` is set.
+    fn visit_store(
+        &mut self,
+        target: &'ast Expression,
+        load: bool,
+        keep_value: bool,
+        compute: impl FnOnce(&mut Self) -> Result<(), ()>,
+    ) -> Result<(), ()> {
+        // Constants can't be written with setproperty, the constructor initialises them instead.
+        let init = match target {
+            Expression::Variable(name) => !self.is_local(name) && self.constants.contains(name),
+            Expression::Property { object, name } => {
+                matches!(**object, Expression::This) && self.constants.contains(name)
+            }
+            _ => false,
+        };
+        let is_super = is_super_property(target);
+
+        let property = match target {
+            Expression::Variable(name) if self.is_local(name) => {
+                if load {
+                    self.get_local(name);
                 }
+                compute(self)?;
 
+                if keep_value {
+                    self.dup();
+                }
                 self.set_local(name);
+                return Ok(());
+            }
+            Expression::Variable(name) => {
+                let index = self.context.add_public_qname(name);
+                self.emit_op(Op::FindProperty { index });
+                self.stack_push();
+                index
+            }
+            Expression::Property { name, .. } if is_super => {
+                self.get_register(0);
+                self.context.add_public_qname(name)
+            }
+            Expression::Property { object, name } => {
+                self.visit_expression(object)?;
+                self.context.add_public_qname(name)
+            }
+            Expression::Index { object, index } => {
+                let name = self.context.add_public_multiname_l();
+                self.visit_expression(object)?;
+                self.visit_expression(index)?;
+
+                return self.store_runtime_property(name, load, keep_value, compute);
+            }
+            Expression::QualifiedName(name) => {
+                let index = self.context.add_rtqname(&name.name);
+                self.find_qualified(name, index, false);
+                self.get_variable(&name.namespace);
+
+                return self.store_runtime_property(index, load, keep_value, compute);
+            }
+            _ => {
+                tracing::error!("Invalid assignment target {:?}", target);
+                return Err(());
+            }
+        };
+
+        if load {
+            self.dup();
+            if is_super {
+                self.emit_op(Op::GetSuper { index: property });
+            } else {
+                self.get_property(property);
             }
         }
+        compute(self)?;
+        self.set_property(
+            if is_super {
+                Op::SetSuper { index: property }
+            } else if init {
+                Op::InitProperty { index: property }
+            } else {
+                Op::SetProperty { index: property }
+            },
+            keep_value,
+        );
 
         Ok(())
     }
 
-    fn visit_function(&mut self, v: &'ast crate::ast::Function) -> Result<(), Self::Error> {
-        // This the setup that the asc.jar/Flex compiler always does in a function, so we do the same.
-        self.emit_op(Op::GetLocal { index: 0 });
-        self.emit_op(Op::PushScope);
+    /// Stores into a property whose multiname takes a part from the stack, which is on top of the object. This is
+    /// the index of `a[i]` or the namespace of `ns::name`.
+    fn store_runtime_property(
+        &mut self,
+        name: Index<Multiname>,
+        load: bool,
+        keep_value: bool,
+        compute: impl FnOnce(&mut Self) -> Result<(), ()>,
+    ) -> Result<(), ()> {
+        if load {
+            // Both the object and the runtime part are needed twice, the runtime part is kept in a register.
+            let register = self.get_temporary();
+            self.set_register(register);
+            self.dup();
+            self.get_register(register);
+            self.get_property(name);
+            self.stack_pop();
 
-        // Parse all statements.
-        for statement in &v.block {
-            self.visit_statement(&statement)?;
+            compute(self)?;
+            self.get_register(register);
+            self.emit_op(Op::Swap);
+            self.free_temporary(register);
+        } else {
+            compute(self)?;
         }
 
-        // Check if return type is void and use stuff accordingly.
-        // FIXME: What if return keyword is used explicitly, then we need to clean it up somewhere. (Otherwise duplicates occur).
-        if v.return_type == Type::Void {
-            self.emit_op(Op::ReturnVoid);
+        self.set_property(Op::SetProperty { index: name }, keep_value);
+        // The runtime part is popped as well.
+        self.stack_pop();
+        Ok(())
+    }
+
+    /// Pushes the object on the scope stack that has a namespace qualified property, the namespace is evaluated
+    /// from the variable that holds it.
+    fn find_qualified(&mut self, name: &QualifiedName, index: Index<Multiname>, strict: bool) {
+        self.get_variable(&name.namespace);
+        // The namespace is replaced by the object.
+        self.emit_op(if strict {
+            Op::FindPropStrict { index }
         } else {
-            // Not going to work yet.
-            todo!()
+            Op::FindProperty { index }
+        });
+    }
+
+    /// Generates a call, the result is only left on the stack if `keep_value` is set.
+    fn visit_call(
+        &mut self,
+        function: &'ast Expression,
+        arguments: &'ast [Expression],
+        keep_value: bool,
+    ) -> Result<(), ()> {
+        let num_args = arguments.len() as u32;
+        let stack_depth = self.current_stack;
+
+        if let Expression::Super = function {
+            // Calls the constructor of the super class on `this`, which results in nothing.
+            self.get_register(0);
+            for argument in arguments {
+                self.visit_expression(argument)?;
+            }
+            self.emit_op(Op::ConstructSuper { num_args });
+            self.current_stack = stack_depth;
+            if keep_value {
+                self.emit_op(Op::PushUndefined);
+                self.stack_push();
+            }
+            return Ok(());
         }
 
-        let method_body = MethodBody {
-            // FIXME: This actually not correct.
-            method: Index::new(0),
-            max_stack: self.max_stack,
-            // NOTE: Plus one required since we are not including "this" object in locals.
-            num_locals: self.locals.len() as u32 + 1,
-            // FIXME: No idea about these two.
-            init_scope_depth: 0,
-            max_scope_depth: 0,
-            // FIXME: Swf crate does not provide an easy way to write opcodes out right now.
-            //        also this requires jump/label resolution.
-            code: Vec::new(),
-            exceptions: Vec::new(),
-            traits: Vec::new(),
+        // Methods are called as a property of their object, so that the object becomes `this`.
+        let is_super = is_super_property(function);
+        let property = match function {
+            Expression::Variable(name) if !self.is_local(name) => {
+                let index = self.context.add_public_qname(name);
+                self.emit_op(Op::FindPropStrict { index });
+                self.stack_push();
+                Some(index)
+            }
+            Expression::Property { name, .. } if is_super => {
+                self.get_register(0);
+                Some(self.context.add_public_qname(name))
+            }
+            Expression::Property { object, name } => {
+                self.visit_expression(object)?;
+                Some(self.context.add_public_qname(name))
+            }
+            Expression::Index { object, index } => {
+                self.visit_expression(object)?;
+                self.visit_expression(index)?;
+                Some(self.context.add_public_multiname_l())
+            }
+            Expression::QualifiedName(name) => {
+                let index = self.context.add_rtqname(&name.name);
+                self.find_qualified(name, index, true);
+                self.get_variable(&name.namespace);
+                Some(index)
+            }
+            _ => {
+                // Any other function value is called without a receiver.
+                self.visit_expression(function)?;
+                self.emit_op(Op::PushNull);
+                self.stack_push();
+                None
+            }
         };
 
-        println!("Method Body: {:?}", method_body);
+        for argument in arguments {
+            self.visit_expression(argument)?;
+        }
+
+        // The call pops the function or object and all arguments.
+        match property {
+            Some(index) if !keep_value => {
+                self.emit_op(if is_super {
+                    Op::CallSuperVoid { index, num_args }
+                } else {
+                    Op::CallPropVoid { index, num_args }
+                });
+                self.current_stack = stack_depth;
+            }
+            Some(index) => {
+                self.emit_op(if is_super {
+                    Op::CallSuper { index, num_args }
+                } else {
+                    Op::CallProperty { index, num_args }
+                });
+                self.current_stack = stack_depth;
+                self.stack_push();
+            }
+            None => {
+                self.emit_op(Op::Call { num_args });
+                self.current_stack = stack_depth;
+                self.stack_push();
+                if !keep_value {
+                    self.pop();
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Applies a binary operator to the value on top of the stack and the right hand side.
+    fn visit_operator(&mut self, operator: &Operator, rhs: &'ast Expression) -> Result<(), ()> {
+        if let Operator::And | Operator::Or = operator {
+            let end_label = self.context.get_next_label();
+
+            // The left hand side is the result if it decides it, otherwise it is replaced by the right hand side.
+            self.dup();
+            if *operator == Operator::And {
+                self.if_false(end_label);
+            } else {
+                self.if_true(end_label);
+            }
+            self.pop();
+            self.visit_expression(rhs)?;
+            self.emit_label(end_label);
+
+            return Ok(());
+        }
+
+        self.visit_expression(rhs)?;
+
+        match operator {
+            Operator::Add => self.emit_op(Op::Add),
+            Operator::Sub => self.emit_op(Op::Subtract),
+            Operator::Mul => self.emit_op(Op::Multiply),
+            Operator::Div => self.emit_op(Op::Divide),
+            Operator::Mod => self.emit_op(Op::Modulo),
+            Operator::BitAnd => self.emit_op(Op::BitAnd),
+            Operator::BitOr => self.emit_op(Op::BitOr),
+            Operator::BitXor => self.emit_op(Op::BitXor),
+            Operator::LeftShift => self.emit_op(Op::LShift),
+            Operator::RightShift => self.emit_op(Op::RShift),
+            Operator::UnsignedRightShift => self.emit_op(Op::URShift),
+            Operator::Less => self.emit_op(Op::LessThan),
+            Operator::LessEqual => self.emit_op(Op::LessEquals),
+            Operator::Greater => self.emit_op(Op::GreaterThan),
+            Operator::GreaterEqual => self.emit_op(Op::GreaterEquals),
+            Operator::Equal => self.emit_op(Op::Equals),
+            Operator::NotEqual => {
+                self.emit_op(Op::Equals);
+                self.emit_op(Op::Not);
+            }
+            Operator::StrictEqual => self.emit_op(Op::StrictEquals),
+            Operator::StrictNotEqual => {
+                self.emit_op(Op::StrictEquals);
+                self.emit_op(Op::Not);
+            }
+            Operator::And | Operator::Or => unreachable!(),
+        }
+
+        // These operations pop two values off the stack and pushes one back. So we pop only once.
+        self.stack_pop();
 
         Ok(())
     }
+
+    /// Evaluates a condition and jumps to `label` if it is `jump_if`.
+    ///
+    /// Logical operators are turned into branches, so no boolean value has to be computed for them.
+    fn visit_branch(
+        &mut self,
+        condition: &'ast Expression,
+        jump_if: bool,
+        label: LabelIndex,
+    ) -> Result<(), ()> {
+        match condition {
+            Expression::UnaryOperation {
+                operator: UnaryOperator::Not,
+                value,
+            } => self.visit_branch(value, !jump_if, label)?,
+            Expression::BinaryOperation {
+                lhs,
+                operator: operator @ (Operator::And | Operator::Or),
+                rhs,
+            } => {
+                // The left hand side decides the result if it is false for `&&` and true for `||`.
+                let decides_if = *operator == Operator::Or;
+
+                if decides_if == jump_if {
+                    self.visit_branch(lhs, jump_if, label)?;
+                    self.visit_branch(rhs, jump_if, label)?;
+                } else {
+                    let skip_label = self.context.get_next_label();
+
+                    self.visit_branch(lhs, decides_if, skip_label)?;
+                    self.visit_branch(rhs, jump_if, label)?;
+                    self.emit_label(skip_label);
+                }
+            }
+            // Comparisons are fused with the branch.
+            Expression::BinaryOperation { lhs, operator, rhs } if operator.is_comparison() => {
+                self.visit_expression(lhs)?;
+                self.visit_expression(rhs)?;
+
+                self.code.push(comparison_branch(operator, jump_if, label));
+                self.stack_pop();
+                self.stack_pop();
+            }
+            _ => {
+                self.visit_expression(condition)?;
+
+                if jump_if {
+                    self.if_true(label);
+                } else {
+                    self.if_false(label);
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Leaves the current statement, running every finally block that is left on the way.
+    fn exit_to(&mut self, exit: Exit) {
+        let leaves_scope = match (exit, self.finally_scopes.last()) {
+            (_, None) => false,
+            (Exit::Jump { depth, .. }, Some(scope)) => scope.jump_depth > depth,
+            (Exit::Return { .. }, Some(_)) => true,
+        };
+
+        if leaves_scope {
+            // The innermost finally block continues the exit once it is done.
+            let continuation = self.context.get_next_label();
+            let scope = self.finally_scopes.last_mut().expect("Scope should exist");
+            scope.exits.push((continuation, Some(exit)));
+
+            let index = scope.exits.len() - 1;
+            let (index_register, finally_label) = (scope.index_register, scope.finally_label);
+            self.push_int(index as i32);
+            self.set_register(index_register);
+            self.jump(finally_label);
+            return;
+        }
+
+        match exit {
+            Exit::Jump { label, depth: _ } => self.jump(label),
+            Exit::Return { value: true } => {
+                self.get_register(self.return_register.expect("Return register should exist"));
+                self.emit_op(Op::ReturnValue);
+                self.stack_pop();
+            }
+            Exit::Return { value: false } => self.emit_op(Op::ReturnVoid),
+        }
+    }
+
+    /// Leaves a try block or catch clause normally, through the finally block if there is one.
+    fn complete_try(&mut self, end_label: LabelIndex, has_finally: bool) {
+        if has_finally {
+            let scope = self.finally_scopes.last().expect("Scope should exist");
+            let (index_register, finally_label) = (scope.index_register, scope.finally_label);
+            self.push_int(0);
+            self.set_register(index_register);
+            self.jump(finally_label);
+        } else {
+            self.jump(end_label);
+        }
+    }
+
+    /// Starts an exception handler. The stack only holds the exception, and the scope stack is empty.
+    fn enter_handler(&mut self, handler_label: LabelIndex) {
+        self.emit_label(handler_label);
+        self.current_stack = 0;
+        self.stack_push();
+
+        // Restore the scope stack the same way the function sets it up.
+        self.scope_depth = 0;
+        self.get_register(0);
+        self.push_scope();
+        if let Some(register) = self.activation_register {
+            self.get_register(register);
+            self.push_scope();
+        }
+    }
+
+    /// Generates the try block and the catch clauses of a try statement, returns the label of the try block.
+    fn visit_try_body(
+        &mut self,
+        body: &'ast [Statement],
+        catches: &'ast [CatchClause],
+        end_label: LabelIndex,
+        has_finally: bool,
+    ) -> Result<LabelIndex, ()> {
+        let try_start = self.context.get_next_label();
+        let try_end = self.context.get_next_label();
+
+        self.emit_label(try_start);
+        self.visit_block(body)?;
+        self.emit_label(try_end);
+        self.complete_try(end_label, has_finally);
+
+        for catch in catches {
+            let handler_label = self.context.get_next_label();
+            let variable_name = self.context.add_public_qname(&catch.variable);
+            let type_name = match &catch.var_type {
+                Type::Any => Index::new(0),
+                Type::Void => {
+                    tracing::error!("A value can not have the type void");
+                    return Err(());
+                }
+                ty => self.type_name(ty),
+            };
+            self.exceptions.push(CompilerException {
+                from: try_start,
+                to: try_end,
+                target: handler_label,
+                variable_name,
+                type_name,
+            });
+
+            self.enter_handler(handler_label);
+            self.coerce(&catch.var_type)?;
+            self.set_local(&catch.variable);
+            self.visit_block(&catch.body)?;
+            self.complete_try(end_label, has_finally);
+        }
+
+        Ok(try_start)
+    }
+
+    fn visit_block(&mut self, block: &'ast [Statement]) -> Result<(), ()> {
+        for v in block {
+            self.visit_statement(v)?;
+        }
+
+        Ok(())
+    }
+
+    /// A marker function to calculate a function's max stack value.
+    fn stack_push(&mut self) {
+        self.current_stack += 1;
+
+        if self.max_stack < self.current_stack {
+            self.max_stack = self.current_stack
+        }
+    }
+
+    /// A marker function to calculate a function's max stack value.
+    fn stack_pop(&mut self) {
+        self.current_stack -= 1;
+    }
+
+    /// Pops an object from the stack and pushes it onto the scope stack.
+    fn push_scope(&mut self) {
+        self.emit_op(Op::PushScope);
+        self.stack_pop();
+        self.scope_depth += 1;
+        self.max_scope_depth = self.max_scope_depth.max(self.scope_depth);
+    }
+
+    /// Removes the object on top of the scope stack.
+    fn pop_scope(&mut self) {
+        self.emit_op(Op::PopScope);
+        self.scope_depth -= 1;
+    }
+
+    fn push_int(&mut self, val: i32) {
+        if let Ok(value) = i8::try_from(val) {
+            // The operand of pushbyte is sign extended.
+            self.emit_op(Op::PushByte { value: value as _ })
+        } else if let Ok(value) = val.try_into() {
+            self.emit_op(Op::PushShort { value })
+        } else {
+            let index = self.context.add_int(val);
+            self.emit_op(Op::PushInt { value: index })
+        }
+
+        self.stack_push()
+    }
+
+    fn push_uint(&mut self, val: u32) {
+        if let Ok(val) = i32::try_from(val) {
+            self.push_int(val)
+        } else {
+            let index = self.context.add_uint(val);
+            self.emit_op(Op::PushUint { value: index });
+            self.stack_push()
+        }
+    }
+
+    fn push_double(&mut self, val: f64) {
+        if val.is_nan() {
+            self.emit_op(Op::PushNaN)
+        } else {
+            let index = self.context.add_double(val);
+            self.emit_op(Op::PushDouble { value: index })
+        }
+
+        self.stack_push()
+    }
+
+    fn push_string(&mut self, val: &String) {
+        let index = self.context.add_string(val);
+        self.emit_op(Op::PushString { value: index });
+        self.stack_push()
+    }
+
+    fn push_bool(&mut self, val: bool) {
+        if val {
+            self.emit_op(Op::PushTrue)
+        } else {
+            self.emit_op(Op::PushFalse)
+        }
+        self.stack_push()
+    }
+
+    /// Constructs a RegExp instance from a literal, the same way asc does.
+    fn push_regexp(&mut self, pattern: &String, flags: &String) {
+        let class = self.context.add_public_qname(&"RegExp".to_owned());
+        self.emit_op(Op::GetLex { index: class });
+        self.stack_push();
+
+        self.push_string(pattern);
+        self.push_string(flags);
+
+        // Pops the class and both arguments, pushes the new instance.
+        self.emit_op(Op::Construct { num_args: 2 });
+        self.stack_pop();
+        self.stack_pop();
+    }
+
+    /// Whether a name refers to a local of this function, rather than something on the scope stack.
+    fn is_local(&self, name: &String) -> bool {
+        self.locals.contains_key(name) || self.slots.contains_key(name)
+    }
+
+    // Gets the value of a local and pushes it onto the stack.
+    fn get_local(&mut self, name: &String) {
+        if let Some(&index) = self.slots.get(name) {
+            self.get_activation();
+            // Replaces the activation with the value.
+            self.emit_op(Op::GetSlot { index });
+        } else {
+            // FIXME: return err, instead of unwrap.
+            self.get_register(*self.locals.get(name).unwrap())
+        }
+    }
+
+    /// Pops a value from the stack and sets it to the specified local.
+    fn set_local(&mut self, name: &String) {
+        if let Some(&index) = self.slots.get(name) {
+            // setslot expects the activation below the value.
+            self.get_activation();
+            self.emit_op(Op::Swap);
+            self.emit_op(Op::SetSlot { index });
+            self.stack_pop();
+            self.stack_pop();
+        } else {
+            self.set_register(*self.locals.get(name).unwrap())
+        }
+    }
+
+    /// Pushes the activation object, which holds the locals that nested functions can access.
+    fn get_activation(&mut self) {
+        // The activation is the second object on the scope stack, after `this`.
+        self.emit_op(Op::GetScopeObject { index: 1 });
+        self.stack_push();
+    }
+
+    /// Returns the declared type of a local.
+    fn local_type(&self, name: &String) -> &'ast Type {
+        self.local_types.get(name).copied().unwrap_or(&Type::Any)
+    }
+
+    /// Generates a `new` expression.
+    fn visit_new(
+        &mut self,
+        class: &'ast Expression,
+        arguments: &'ast [Expression],
+    ) -> Result<(), ()> {
+        let num_args = arguments.len() as u32;
+        let stack_depth = self.current_stack;
+
+        // Named classes are looked up by name, unless they're a property of some object.
+        let (object, property) = match class {
+            Expression::Variable(name) if !self.is_local(name) => {
+                (None, Some(self.context.add_public_qname(name)))
+            }
+            Expression::Property { object, name } => match self.package_name(object) {
+                Some(package) => (None, Some(self.context.add_package_qname(&package, name))),
+                None => (Some(object), Some(self.context.add_public_qname(name))),
+            },
+            _ => (None, None),
+        };
+
+        match property {
+            Some(index) => {
+                match object {
+                    Some(object) => self.visit_expression(object)?,
+                    None => {
+                        self.emit_op(Op::FindPropStrict { index });
+                        self.stack_push();
+                    }
+                }
+                for argument in arguments {
+                    self.visit_expression(argument)?;
+                }
+                self.emit_op(Op::ConstructProp { index, num_args });
+            }
+            None => {
+                // Any other value is used as the class itself.
+                self.visit_expression(class)?;
+                for argument in arguments {
+                    self.visit_expression(argument)?;
+                }
+                self.emit_op(Op::Construct { num_args });
+            }
+        }
+
+        // Construction pops the class or the object it's found on and all arguments.
+        self.current_stack = stack_depth;
+        self.stack_push();
+
+        Ok(())
+    }
+
+    /// Returns the name of a package if the expression is a dotted name like `flash.display`.
+    ///
+    /// FIXME: Use the imports of the file, which are not parsed yet. Until then packages are told apart from objects
+    /// by their names: the root can't be a local or a member, and every part has to be lowercase, like package names
+    /// by convention. Other chains such as `this.factory` or `someGlobalObj.lib` are evaluated as objects.
+    fn package_name(&self, v: &Expression) -> Option<String> {
+        let is_package_part = |name: &String| !name.chars().any(char::is_uppercase);
+
+        match v {
+            Expression::Variable(name)
+                if !self.is_local(name)
+                    && !self.members.contains(name)
+                    && is_package_part(name) =>
+            {
+                Some(name.clone())
+            }
+            Expression::Property { object, name } if is_package_part(name) => {
+                let package = self.package_name(object)?;
+                Some(format!("{}.{}", package, name))
+            }
+            _ => None,
+        }
+    }
+
+    /// Returns the declared type of an assignment target, only locals are coerced to their type.
+    fn target_type(&self, target: &'ast Expression) -> Option<&'ast Type> {
+        match target {
+            Expression::Variable(name) if self.is_local(name) => Some(self.local_type(name)),
+            _ => None,
+        }
+    }
+
+    /// Pushes the value of a variable, names that are not locals are looked up on the scope stack.
+    fn get_variable(&mut self, name: &String) {
+        if self.is_local(name) {
+            self.get_local(name)
+        } else {
+            let index = self.context.add_public_qname(name);
+            self.emit_op(Op::GetLex { index });
+            self.stack_push()
+        }
+    }
+
+    /// Replaces the object on top of the stack with the value of one of its properties.
+    fn get_property(&mut self, index: Index<Multiname>) {
+        self.emit_op(Op::GetProperty { index });
+    }
+
+    /// Pops a value and an object from the stack and sets the property of the object with `op`, which is a
+    /// setproperty, initproperty or setsuper. The value is only left on the stack if `keep_value` is set.
+    fn set_property(&mut self, op: Op, keep_value: bool) {
+        // setproperty consumes the value, so a copy of it waits in a register meanwhile.
+        let kept_value = keep_value.then(|| {
+            self.dup();
+            let register = self.get_temporary();
+            self.set_register(register);
+            register
+        });
+
+        self.emit_op(op);
+        self.stack_pop();
+        self.stack_pop();
+
+        if let Some(register) = kept_value {
+            self.get_register(register);
+            self.free_temporary(register);
+        }
+    }
+
+    /// Allocates a register for an intermediate value, it has to be released with `free_temporary` again.
+    fn get_temporary(&mut self) -> u32 {
+        self.temporaries.pop().unwrap_or_else(|| {
+            self.num_locals += 1;
+            self.num_locals - 1
+        })
+    }
+
+    /// Releases a register allocated with `get_temporary`.
+    fn free_temporary(&mut self, index: u32) {
+        self.emit_op(Op::Kill { index });
+        self.temporaries.push(index);
+    }
+
+    fn get_register(&mut self, index: u32) {
+        self.emit_op(Op::GetLocal { index });
+        self.stack_push()
+    }
+
+    fn set_register(&mut self, index: u32) {
+        self.emit_op(Op::SetLocal { index });
+        self.stack_pop();
+    }
+
+    /// Converts the value on top of the stack into the given type.
+    fn coerce(&mut self, ty: &Type) -> Result<(), ()> {
+        match ty {
+            Type::Any => self.emit_op(Op::CoerceA),
+            Type::Int => self.emit_op(Op::ConvertI),
+            Type::Uint => self.emit_op(Op::ConvertU),
+            Type::Number => self.emit_op(Op::ConvertD),
+            Type::Bool => self.emit_op(Op::ConvertB),
+            Type::Other(_) => {
+                let index = self.type_name(ty);
+                self.emit_op(Op::Coerce { index })
+            }
+            Type::Void => {
+                // Only a return type can be void, there are no values of it.
+                tracing::error!("A value can not have the type void");
+                return Err(());
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Returns the multiname of a type.
+    fn type_name(&mut self, ty: &Type) -> Index<Multiname> {
+        // FIXME: Classes outside of the top level package.
+        let name = match ty {
+            Type::Int => "int",
+            Type::Uint => "uint",
+            Type::Number => "Number",
+            Type::Bool => "Boolean",
+            Type::Other(name) => name,
+            Type::Any | Type::Void => unreachable!("{:?} is not a class", ty),
+        };
+
+        self.context.add_public_qname(&name.to_owned())
+    }
+
+    /// Returns the multiname of a type in a method signature or a trait, where zero stands for any type.
+    fn signature_type(&mut self, ty: &Type) -> Index<Multiname> {
+        match ty {
+            Type::Any => Index::new(0),
+            Type::Void => self.context.add_public_qname(&"void".to_owned()),
+            ty => self.type_name(ty),
+        }
+    }
+
+    /// Generates a function defined inside this one and pushes a closure of it.
+    fn visit_local_function(&mut self, function: &'ast LocalFunction) -> Result<(), ()> {
+        let mut var_visitor = LocalResolverVisitor::default();
+        var_visitor.visit_local_function(function)?;
+
+        let mut function_gen = self.nested_generator(var_visitor);
+        let index = function_gen.generate_method(
+            function.name.as_ref(),
+            &function.arguments,
+            &function.return_type,
+            &function.block,
+        )?;
+
+        // The closure keeps the current scope stack, which is how it reaches the activation of this function.
+        self.emit_op(Op::NewFunction { index });
+        self.stack_push();
+
+        Ok(())
+    }
+
+    /// Generates a function expression, which can refer to itself if it has a name.
+    fn visit_function_expression(&mut self, function: &'ast LocalFunction) -> Result<(), ()> {
+        let Some(name) = &function.name else {
+            return self.visit_local_function(function);
+        };
+
+        // As in section 13 of ECMA-262, the name is bound in a new object that only the closure has on its scope
+        // stack, so that it is not visible in this function.
+        self.emit_op(Op::NewObject { num_args: 0 });
+        self.stack_push();
+        let index = self.scope_depth as u8;
+        self.push_scope();
+
+        self.visit_local_function(function)?;
+        self.dup();
+        self.emit_op(Op::GetScopeObject { index });
+        self.stack_push();
+        self.emit_op(Op::Swap);
+        let name = self.context.add_public_qname(name);
+        self.set_property(Op::SetProperty { index: name }, false);
+
+        self.pop_scope();
+
+        Ok(())
+    }
+
+    /// Creates the generator of a function nested in this one.
+    fn nested_generator(
+        &mut self,
+        variables: LocalResolverVisitor<'ast>,
+    ) -> FunctionGenerator<'ast, '_> {
+        // Names of this function are still objects inside the nested one, reached through the scope stack.
+        let members = self
+            .members
+            .iter()
+            .chain(self.locals.keys())
+            .chain(self.slots.keys())
+            .copied()
+            .collect();
+
+        let init_scope_depth = self.init_scope_depth + self.scope_depth;
+        let mut generator = FunctionGenerator::new(variables, &mut self.context);
+        generator.members = members;
+        // The closure starts out with the scope stack of this function.
+        generator.init_scope_depth = init_scope_depth;
+        generator
+    }
+
+    /// Generates the body of a function and adds it as a method.
+    fn generate_method(
+        &mut self,
+        name: Option<&String>,
+        arguments: &'ast [Argument],
+        return_type: &'ast Type,
+        block: &'ast [Statement],
+    ) -> Result<Index<Method>, ()> {
+        self.return_type = Some(return_type);
+
+        // This the setup that the asc.jar/Flex compiler always does in a function, so we do the same.
+        self.get_register(0);
+        self.push_scope();
+
+        if let Some(register) = self.activation_register {
+            self.emit_op(Op::NewActivation);
+            self.stack_push();
+            self.dup();
+            self.set_register(register);
+            self.push_scope();
+
+            // Arguments are passed in registers, captured ones are copied into the activation.
+            for (register, argument) in (1..).zip(arguments) {
+                if let Some(&index) = self.slots.get(&argument.name) {
+                    self.get_activation();
+                    self.get_register(register);
+                    self.emit_op(Op::SetSlot { index });
+                    self.stack_pop();
+                    self.stack_pop();
+                }
+            }
+        }
+
+        // Nested function declarations can be called before the statement that declares them.
+        for function in std::mem::take(&mut self.functions) {
+            self.visit_local_function(function)?;
+            self.set_local(
+                function
+                    .name
+                    .as_ref()
+                    .expect("Function declarations should have a name"),
+            );
+        }
+
+        // Parse all statements.
+        for statement in block {
+            self.visit_statement(statement)?;
+        }
+
+        // Check if return type is void and use stuff accordingly.
+        // NOTE: After an explicit return this is unreachable, which the verifier does not mind.
+        if *return_type == Type::Void {
+            self.emit_op(Op::ReturnVoid);
+        } else {
+            // FIXME: asc reports an error instead if the end of the function is reachable.
+            self.emit_op(Op::PushUndefined);
+            self.stack_push();
+            self.coerce(return_type)?;
+            self.emit_op(Op::ReturnValue);
+            self.stack_pop();
+        }
+
+        // Captured variables are slot traits of the activation.
+        let mut slots: Vec<_> = self
+            .slots
+            .iter()
+            .map(|(name, slot)| (*slot, *name))
+            .collect();
+        slots.sort_unstable();
+        let traits = slots
+            .into_iter()
+            .map(|(slot_id, name)| Trait {
+                name: self.context.add_public_qname(name),
+                kind: TraitKind::Slot {
+                    slot_id,
+                    type_name: self.signature_type(self.local_type(name)),
+                    value: None,
+                },
+                metadata: Vec::new(),
+            })
+            .collect();
+
+        let (code, exceptions) = resolver::resolve(&self.code, &self.exceptions);
+        let method_body = MethodBody {
+            // Filled in once the method is added.
+            method: Index::new(0),
+            max_stack: self.max_stack,
+            num_locals: self.num_locals,
+            init_scope_depth: self.init_scope_depth,
+            max_scope_depth: self.init_scope_depth + self.max_scope_depth,
+            code,
+            exceptions,
+            traits,
+        };
+
+        let params = arguments
+            .iter()
+            .map(|argument| MethodParam {
+                name: None,
+                kind: self.signature_type(&argument.value_type),
+                default_value: None,
+            })
+            .collect();
+        let method = Method {
+            name: self.context.add_string(name.unwrap_or(&String::new())),
+            params,
+            return_type: self.signature_type(return_type),
+            flags: if self.activation_register.is_some() {
+                MethodFlags::NEED_ACTIVATION
+            } else {
+                MethodFlags::empty()
+            },
+            body: None,
+        };
+
+        Ok(self.context.add_method(method, method_body))
+    }
+}
+
+/// Checks if an expression is a property of the super class, like `super.name`, which is reached through `this`.
+fn is_super_property(v: &Expression) -> bool {
+    matches!(v, Expression::Property { object, .. } if matches!(**object, Expression::Super))
+}
+
+/// Returns the branch that compares the two values on top of the stack and jumps to `label` if the result is
+/// `jump_if`.
+fn comparison_branch(operator: &Operator, jump_if: bool, label: LabelIndex) -> CompilerOp {
+    // Comparisons involving NaN are always false, so `!(a < b)` is not the same as `a >= b`. The negated
+    // branches take care of that.
+    match (operator, jump_if) {
+        (Operator::Less, true) => CompilerOp::IfLt(label),
+        (Operator::Less, false) => CompilerOp::IfNlt(label),
+        (Operator::LessEqual, true) => CompilerOp::IfLe(label),
+        (Operator::LessEqual, false) => CompilerOp::IfNle(label),
+        (Operator::Greater, true) => CompilerOp::IfGt(label),
+        (Operator::Greater, false) => CompilerOp::IfNgt(label),
+        (Operator::GreaterEqual, true) => CompilerOp::IfGe(label),
+        (Operator::GreaterEqual, false) => CompilerOp::IfNge(label),
+        (Operator::Equal, true) | (Operator::NotEqual, false) => CompilerOp::IfEq(label),
+        (Operator::Equal, false) | (Operator::NotEqual, true) => CompilerOp::IfNe(label),
+        (Operator::StrictEqual, true) | (Operator::StrictNotEqual, false) => {
+            CompilerOp::IfStrictEq(label)
+        }
+        (Operator::StrictEqual, false) | (Operator::StrictNotEqual, true) => {
+            CompilerOp::IfStrictNe(label)
+        }
+        _ => unreachable!("{:?} is not a comparison", operator),
+    }
+}
+
+impl<'ast, 'a> Visitor<'ast> for FunctionGenerator<'ast, 'a> {
+    type Error = ();
+
+    fn visit_expression(&mut self, v: &'ast crate::ast::Expression) -> Result<(), Self::Error> {
+        match v {
+            Expression::BinaryOperation { lhs, operator, rhs } => {
+                self.visit_expression(lhs)?;
+                self.visit_operator(operator, rhs)?;
+            }
+            Expression::UnaryOperation {
+                operator: UnaryOperator::Not,
+                value,
+            } => {
+                self.visit_expression(value)?;
+                self.emit_op(Op::Not);
+            }
+            Expression::UnaryOperation {
+                operator: UnaryOperator::BitNot,
+                value,
+            } => {
+                self.visit_expression(value)?;
+                self.emit_op(Op::BitNot);
+            }
+            Expression::UnaryOperation {
+                operator: UnaryOperator::Negate,
+                value,
+            } => {
+                self.visit_expression(value)?;
+                self.emit_op(Op::Negate);
+            }
+            Expression::UnaryOperation {
+                operator: UnaryOperator::Plus,
+                value,
+            } => {
+                self.visit_expression(value)?;
+                self.emit_op(Op::ConvertD);
+            }
+            Expression::Ternary {
+                condition,
+                if_true,
+                if_false,
+            } => {
+                let else_label = self.context.get_next_label();
+                let end_label = self.context.get_next_label();
+
+                self.visit_branch(condition, false, else_label)?;
+                self.visit_expression(if_true)?;
+                self.jump(end_label);
+
+                // Only one of the values is on the stack at the end.
+                self.stack_pop();
+                self.emit_label(else_label);
+                self.visit_expression(if_false)?;
+                self.emit_label(end_label);
+            }
+            Expression::Update {
+                operator,
+                prefix,
+                target,
+            } => self.visit_update(operator, *prefix, target, true)?,
+            Expression::Assignment {
+                target,
+                operator,
+                value,
+            } => self.visit_assignment(target, operator.as_ref(), value, true)?,
+            Expression::Integer(val) => self.push_int(*val),
+            Expression::Uint(val) => self.push_uint(*val),
+            Expression::Number(val) => self.push_double(*val),
+            Expression::String(val) => self.push_string(val),
+            Expression::Bool(x) => self.push_bool(*x),
+            Expression::RegExp { pattern, flags } => self.push_regexp(pattern, flags),
+            Expression::Array(elements) => {
+                let stack_depth = self.current_stack;
+                for element in elements {
+                    match element {
+                        Some(element) => self.visit_expression(element)?,
+                        None => {
+                            self.emit_op(Op::PushUndefined);
+                            self.stack_push();
+                        }
+                    }
+                }
+
+                // Pops all elements and pushes the array.
+                self.emit_op(Op::NewArray {
+                    num_args: elements.len() as u32,
+                });
+                self.current_stack = stack_depth;
+                self.stack_push();
+            }
+            Expression::Object(properties) => {
+                let stack_depth = self.current_stack;
+                for (name, value) in properties {
+                    self.push_string(name);
+                    self.visit_expression(value)?;
+                }
+
+                // Pops all names and values and pushes the object.
+                self.emit_op(Op::NewObject {
+                    num_args: properties.len() as u32,
+                });
+                self.current_stack = stack_depth;
+                self.stack_push();
+            }
+            Expression::Variable(name) => self.get_variable(name),
+            Expression::This => self.get_register(0),
+            Expression::Super => {
+                tracing::error!("super can only be used for properties and the constructor");
+                return Err(());
+            }
+            Expression::Null => {
+                self.emit_op(Op::PushNull);
+                self.stack_push();
+            }
+            Expression::Property { object, name } => {
+                let index = self.context.add_public_qname(name);
+                if is_super_property(v) {
+                    self.get_register(0);
+                    self.emit_op(Op::GetSuper { index });
+                } else {
+                    self.visit_expression(object)?;
+                    self.get_property(index);
+                }
+            }
+            Expression::Index { object, index } => {
+                self.visit_expression(object)?;
+                self.visit_expression(index)?;
+                let name = self.context.add_public_multiname_l();
+                self.get_property(name);
+                // The index is popped as well.
+                self.stack_pop();
+            }
+            Expression::Function(function) => self.visit_function_expression(function)?,
+            Expression::Call {
+                function,
+                arguments,
+            } => self.visit_call(function, arguments, true)?,
+            Expression::New { class, arguments } => self.visit_new(class, arguments)?,
+            // Configuration constants are already replaced, any other namespace is a value, e.g. `mx_internal`.
+            Expression::QualifiedName(name) => {
+                let index = self.context.add_rtqname(&name.name);
+                self.find_qualified(name, index, true);
+                self.get_variable(&name.namespace);
+                self.get_property(index);
+                // The namespace is popped as well.
+                self.stack_pop();
+            }
+        }
+
+        Ok(())
+    }
+
+    fn visit_statement(&mut self, v: &'ast crate::ast::Statement) -> Result<(), Self::Error> {
+        match v {
+            Statement::Variable {
+                name,
+                var_type,
+                value,
+            } => {
+                // Emit expression code.
+                self.visit_expression(value)?;
+
+                // Emit a coerce operation.
+                self.coerce(var_type)?;
+
+                self.set_local(name);
+            }
+            Statement::Block(block) => self.visit_block(block)?,
+            Statement::If {
+                condition,
+                body,
+                else_body,
+            } => {
+                let else_label = self.context.get_next_label();
+
+                self.visit_branch(condition, false, else_label)?;
+                self.visit_statement(body)?;
+
+                if let Some(else_body) = else_body {
+                    let end_label = self.context.get_next_label();
+
+                    // Skip over the else branch once the body is done.
+                    self.jump(end_label);
+                    self.emit_label(else_label);
+                    self.visit_statement(else_body)?;
+                    self.emit_label(end_label);
+                } else {
+                    self.emit_label(else_label);
+                }
+            }
+            // Loops are laid out like asc does, with the condition after the body, so every iteration needs
+            // only a single branch.
+            Statement::While { condition, body } => {
+                let body_label = self.context.get_next_label();
+                let condition_label = self.context.get_next_label();
+                let end_label = self.context.get_next_label();
+
+                self.jump(condition_label);
+                self.emit_label(body_label);
+                self.visit_loop_body(body, end_label, condition_label)?;
+
+                self.emit_label(condition_label);
+                self.visit_branch(condition, true, body_label)?;
+                self.emit_label(end_label);
+            }
+            Statement::DoWhile { body, condition } => {
+                let body_label = self.context.get_next_label();
+                let condition_label = self.context.get_next_label();
+                let end_label = self.context.get_next_label();
+
+                self.emit_label(body_label);
+                self.visit_loop_body(body, end_label, condition_label)?;
+
+                self.emit_label(condition_label);
+                self.visit_branch(condition, true, body_label)?;
+                self.emit_label(end_label);
+            }
+            Statement::For {
+                init,
+                condition,
+                update,
+                body,
+            } => {
+                if let Some(init) = init {
+                    self.visit_statement(init)?;
+                }
+
+                let body_label = self.context.get_next_label();
+                let update_label = self.context.get_next_label();
+                let condition_label = self.context.get_next_label();
+                let end_label = self.context.get_next_label();
+
+                self.jump(condition_label);
+                self.emit_label(body_label);
+                self.visit_loop_body(body, end_label, update_label)?;
+
+                self.emit_label(update_label);
+                if let Some(update) = update {
+                    self.visit_discarded(update)?;
+                }
+
+                self.emit_label(condition_label);
+                if let Some(condition) = condition {
+                    self.visit_branch(condition, true, body_label)?;
+                } else {
+                    self.jump(body_label);
+                }
+                self.emit_label(end_label);
+            }
+            Statement::ForIn {
+                each,
+                variable,
+                object,
+                body,
+            } => {
+                let registers = self.for_in_registers[&(v as *const _)];
+
+                let body_label = self.context.get_next_label();
+                let condition_label = self.context.get_next_label();
+                let end_label = self.context.get_next_label();
+
+                // The index starts at zero, hasnext2 advances it to the next property.
+                self.visit_expression(object)?;
+                self.emit_op(Op::CoerceA);
+                self.set_register(registers.object);
+                self.push_int(0);
+                self.set_register(registers.index);
+                self.jump(condition_label);
+
+                self.emit_label(body_label);
+                let ty = match variable {
+                    ForInVariable::Declaration { var_type, .. } => Some(var_type),
+                    ForInVariable::Target(target) => self.target_type(target),
+                };
+                let next = |this: &mut Self| {
+                    this.get_register(registers.object);
+                    this.get_register(registers.index);
+                    // Pops the object and index, pushes the name or value.
+                    this.emit_op(if *each { Op::NextValue } else { Op::NextName });
+                    this.stack_pop();
+                    if let Some(ty) = ty {
+                        this.coerce(ty)?;
+                    }
+                    Ok(())
+                };
+                match variable {
+                    ForInVariable::Declaration { name, .. } => {
+                        next(self)?;
+                        self.set_local(name);
+                    }
+                    // Assigned like any other variable, it may not be a local at all.
+                    ForInVariable::Target(target) => {
+                        self.visit_store(target, false, false, next)?
+                    }
+                }
+                self.visit_loop_body(body, end_label, condition_label)?;
+
+                self.emit_label(condition_label);
+                self.emit_op(Op::HasNext2 {
+                    object_register: registers.object,
+                    index_register: registers.index,
+                });
+                self.stack_push();
+                self.if_true(body_label);
+                self.emit_label(end_label);
+
+                // Let the verifier know the registers are no longer used.
+                self.emit_op(Op::Kill {
+                    index: registers.object,
+                });
+                self.emit_op(Op::Kill {
+                    index: registers.index,
+                });
+            }
+            Statement::Switch {
+                discriminant,
+                cases,
+            } => {
+                let register = self.switch_registers[&(v as *const _)];
+
+                self.visit_expression(discriminant)?;
+                self.set_register(register);
+
+                let case_labels = cases
+                    .iter()
+                    .map(|_| self.context.get_next_label())
+                    .collect::<Vec<_>>();
+                let compare_label = self.context.get_next_label();
+                let dispatch_label = self.context.get_next_label();
+                let end_label = self.context.get_next_label();
+
+                // Case bodies come first, in order, so that they can fall through into each other.
+                self.jump(compare_label);
+                self.push_jump_target(end_label, None, true);
+                for (case, label) in cases.iter().zip(&case_labels) {
+                    self.emit_label(*label);
+                    for statement in &case.body {
+                        if let Err(e) = self.visit_statement(statement) {
+                            self.pop_jump_target();
+                            return Err(e);
+                        }
+                    }
+                }
+                self.pop_jump_target();
+                self.jump(end_label);
+
+                // Compare against every case in order, which results in the index of the matching case.
+                self.emit_label(compare_label);
+                for (index, case) in cases.iter().enumerate() {
+                    let Some(value) = &case.value else {
+                        continue;
+                    };
+                    let next_label = self.context.get_next_label();
+
+                    self.visit_expression(value)?;
+                    self.get_register(register);
+                    self.if_strict_ne(next_label);
+                    self.push_int(index as i32);
+                    self.jump(dispatch_label);
+                    // The index is only on the stack when jumping to the dispatch.
+                    self.stack_pop();
+                    self.emit_label(next_label);
+                }
+
+                // Without a match, go to the default case. Without a default case, the index is out of range
+                // and lookupswitch goes to the end instead.
+                let default_index = cases
+                    .iter()
+                    .position(|case| case.value.is_none())
+                    .unwrap_or(cases.len());
+                self.push_int(default_index as i32);
+
+                self.emit_label(dispatch_label);
+                if case_labels.is_empty() {
+                    // lookupswitch needs at least one case.
+                    self.pop();
+                } else {
+                    self.code.push(CompilerOp::LookupSwitch {
+                        default: end_label,
+                        cases: case_labels,
+                    });
+                    self.stack_pop();
+                }
+                self.emit_label(end_label);
+
+                self.emit_op(Op::Kill { index: register });
+            }
+            Statement::Labeled { label, body } => {
+                self.pending_labels.push(label.clone());
+
+                match **body {
+                    // Loops claim the label themselves, so that it can be continued.
+                    Statement::While { .. }
+                    | Statement::DoWhile { .. }
+                    | Statement::For { .. }
+                    | Statement::ForIn { .. }
+                    | Statement::Labeled { .. } => self.visit_statement(body)?,
+                    _ => {
+                        let end_label = self.context.get_next_label();
+
+                        self.push_jump_target(end_label, None, false);
+                        let result = self.visit_statement(body);
+                        self.pop_jump_target();
+                        result?;
+
+                        self.emit_label(end_label);
+                    }
+                }
+            }
+            Statement::Try {
+                body,
+                catches,
+                finally,
+            } => {
+                let end_label = self.context.get_next_label();
+                let registers = self.finally_registers.get(&(v as *const _)).copied();
+
+                if let Some(registers) = registers {
+                    let finally_label = self.context.get_next_label();
+                    let rethrow_label = self.context.get_next_label();
+                    self.finally_scopes.push(FinallyScope {
+                        jump_depth: self.jump_targets.len(),
+                        finally_label,
+                        index_register: registers.index,
+                        exits: vec![(end_label, None), (rethrow_label, None)],
+                    });
+                }
+
+                let result = self.visit_try_body(body, catches, end_label, finally.is_some());
+                let scope = registers.and_then(|_| self.finally_scopes.pop());
+                let try_start = result?;
+
+                if let (Some(finally), Some(registers), Some(scope)) = (finally, registers, scope) {
+                    // Exceptions from the try block or a catch clause run the finally block and are then
+                    // rethrown.
+                    let handler_label = self.context.get_next_label();
+                    self.exceptions.push(CompilerException {
+                        from: try_start,
+                        to: handler_label,
+                        target: handler_label,
+                        variable_name: Index::new(0),
+                        type_name: Index::new(0),
+                    });
+                    self.enter_handler(handler_label);
+                    self.set_register(registers.exception);
+                    // The finally block directly follows.
+                    self.push_int(1);
+                    self.set_register(registers.index);
+
+                    self.emit_label(scope.finally_label);
+                    self.visit_block(finally)?;
+
+                    self.get_register(registers.index);
+                    self.code.push(CompilerOp::LookupSwitch {
+                        default: end_label,
+                        cases: scope.exits.iter().map(|(label, _)| *label).collect(),
+                    });
+                    self.stack_pop();
+
+                    for (label, exit) in scope.exits.into_iter().skip(1) {
+                        self.emit_label(label);
+                        match exit {
+                            // Continue leaving, which may go through more finally blocks.
+                            Some(exit) => self.exit_to(exit),
+                            None => {
+                                self.get_register(registers.exception);
+                                self.emit_op(Op::Throw);
+                                self.stack_pop();
+                            }
+                        }
+                    }
+                }
+                self.emit_label(end_label);
+
+                if let Some(registers) = registers {
+                    self.emit_op(Op::Kill {
+                        index: registers.exception,
+                    });
+                    self.emit_op(Op::Kill {
+                        index: registers.index,
+                    });
+                }
+            }
+            Statement::Throw(value) => {
+                self.visit_expression(value)?;
+                self.emit_op(Op::Throw);
+                self.stack_pop();
+            }
+            Statement::Return(value) => {
+                let return_type = self
+                    .return_type
+                    .expect("Return should be inside a function");
+
+                match value {
+                    Some(_) if *return_type == Type::Void => {
+                        tracing::error!("A void function can not return a value");
+                        return Err(());
+                    }
+                    Some(value) => {
+                        self.visit_expression(value)?;
+                        self.coerce(return_type)?;
+
+                        if self.finally_scopes.is_empty() {
+                            self.emit_op(Op::ReturnValue);
+                            self.stack_pop();
+                        } else {
+                            // The value has to survive the finally blocks.
+                            self.set_register(
+                                self.return_register.expect("Return register should exist"),
+                            );
+                            self.exit_to(Exit::Return { value: true });
+                        }
+                    }
+                    None if !matches!(return_type, Type::Void | Type::Any) => {
+                        tracing::error!(
+                            "A function returning {:?} must return a value",
+                            return_type
+                        );
+                        return Err(());
+                    }
+                    None => self.exit_to(Exit::Return { value: false }),
+                }
+            }
+            Statement::Break(label) => {
+                let Some(depth) = self.find_jump_target(label.as_ref(), false) else {
+                    tracing::error!("Break target {:?} not found", label);
+                    return Err(());
+                };
+
+                let label = self.jump_targets[depth].break_label;
+                self.exit_to(Exit::Jump { label, depth });
+            }
+            Statement::Continue(label) => {
+                let Some(depth) = self.find_jump_target(label.as_ref(), true) else {
+                    tracing::error!("Continue target {:?} not found", label);
+                    return Err(());
+                };
+
+                let label = self.jump_targets[depth]
+                    .continue_label
+                    .expect("Target should be a loop");
+                self.exit_to(Exit::Jump { label, depth });
+            }
+            Statement::Expression(value) => self.visit_discarded(value)?,
+            // Nested functions are created when the function starts, see `generate_method`.
+            Statement::Function(_) => {}
+            Statement::Config { condition, .. } => {
+                tracing::error!(
+                    "Conditional compilation with {} must be resolved before code generation",
+                    condition
+                );
+                return Err(());
+            }
+        }
+
+        Ok(())
+    }
+
+    fn visit_function(&mut self, v: &'ast crate::ast::Function) -> Result<(), Self::Error> {
+        self.generate_method(Some(&v.name), &v.arguments, &v.return_type, &v.block)?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::as3::parse_package;
+
+    /// Parses a class with the given members, followed by a method with the given body.
+    fn parse_class(members: &str, body: &str) -> Class {
+        let source = format!(
+            "package {{ public class Test {{ {members} public function test(): void {{ {body} }} }} }}"
+        );
+        let (package, diagnostics) = parse_package(&source);
+        assert!(diagnostics.is_empty(), "{:?}", diagnostics);
+
+        package.unwrap().classes.remove(0)
+    }
+
+    /// Generates the code of the last method of a class, and returns the generator to inspect it.
+    fn generate<'ast>(
+        class: &'ast Class,
+        constructor: bool,
+        context: &'ast mut ConstantPoolContext,
+    ) -> FunctionGenerator<'ast, 'ast> {
+        let function = class.functions.last().unwrap();
+        let mut variables = LocalResolverVisitor::default();
+        variables.visit_function(function).unwrap();
+
+        let mut generator =
+            FunctionGenerator::new(variables, context).with_class(class, constructor);
+        generator.visit_function(function).unwrap();
+        generator
+    }
+
+    /// Returns the code of a generator, without any labels or branches.
+    fn raw_ops(generator: &FunctionGenerator) -> Vec<Op> {
+        generator
+            .code
+            .iter()
+            .filter_map(|op| match op {
+                CompilerOp::Raw(op) => Some(op.clone()),
+                _ => None,
+            })
+            .collect()
+    }
+
+    fn raw(op: Op) -> CompilerOp {
+        CompilerOp::Raw(op)
+    }
+
+    fn label(index: u32) -> CompilerOp {
+        CompilerOp::Label(LabelIndex(index))
+    }
+
+    /// Returns the value of a constant pool entry, the first entry has index one.
+    fn pool_entry<T: Copy>(pool: &[T], index: &Index<T>) -> T {
+        pool[index.index as usize - 1]
+    }
+
+    #[test]
+    fn numeric_literals() {
+        // Small integers are operands of the instruction.
+        for (source, op) in [
+            ("127", Op::PushByte { value: 127 }),
+            ("128", Op::PushShort { value: 128 }),
+            ("32767", Op::PushShort { value: 32767 }),
+        ] {
+            assert_eq!(value_ops(source).0, [op], "{}", source);
+        }
+
+        // Bigger ones and any other number are added to the constant pool.
+        let (ops, context) = value_ops("32768");
+        let [Op::PushInt { value }] = &ops[..] else {
+            panic!("Expected pushint, got {:?}", ops);
+        };
+        assert_eq!(pool_entry(&context.constant_pool().ints, value), 32768);
+
+        let (ops, context) = value_ops("0xFFFFFFFF");
+        let [Op::PushUint { value }] = &ops[..] else {
+            panic!("Expected pushuint, got {:?}", ops);
+        };
+        assert_eq!(pool_entry(&context.constant_pool().uints, value), u32::MAX);
+
+        for (source, number) in [("1.5", 1.5), ("1e300", 1e300), ("4294967296", 4294967296.0)] {
+            let (ops, context) = value_ops(source);
+            let [Op::PushDouble { value }] = &ops[..] else {
+                panic!("Expected pushdouble for {}, got {:?}", source, ops);
+            };
+            assert_eq!(pool_entry(&context.constant_pool().doubles, value), number);
+        }
+    }
+
+    #[test]
+    fn signed_integers_and_special_doubles() {
+        let class = parse_class("", "");
+        let mut context = ConstantPoolContext::default();
+        let mut generator = generate(&class, false, &mut context);
+        generator.code.clear();
+
+        generator.push_int(-128);
+        generator.push_int(-129);
+        generator.push_int(-32769);
+        generator.push_double(0.0);
+        generator.push_double(-0.0);
+        generator.push_double(0.0);
+        generator.push_double(f64::NAN);
+
+        // Both zeros get an entry, NaN has an instruction of its own.
+        let ints = generator.context.constant_pool().ints.clone();
+        let doubles = generator.context.constant_pool().doubles.clone();
+        assert_eq!(ints, [-32769]);
+        assert_eq!(doubles.len(), 2);
+        assert!(doubles[0].is_sign_positive() && doubles[1].is_sign_negative());
+        assert_eq!(
+            raw_ops(&generator),
+            [
+                // pushbyte is sign extended.
+                Op::PushByte { value: 0x80 },
+                Op::PushShort { value: -129 },
+                Op::PushInt {
+                    value: Index::new(1)
+                },
+                Op::PushDouble {
+                    value: Index::new(1)
+                },
+                Op::PushDouble {
+                    value: Index::new(2)
+                },
+                Op::PushDouble {
+                    value: Index::new(1)
+                },
+                Op::PushNaN,
+            ]
+        );
+    }
+
+    #[test]
+    fn regexp_literals() {
+        for (source, pattern, flags) in [("/ab+c/gi", "ab+c", "gi"), ("/a\\/b/", "a\\/b", "")] {
+            let (ops, mut context) = value_ops(source);
+            let class = context.add_public_qname(&"RegExp".to_owned());
+            let [pattern, flags] =
+                [pattern, flags].map(|value| context.add_string(&value.to_owned()));
+
+            // The pattern and flags are passed to the RegExp constructor, even if there are no flags.
+            assert_eq!(
+                ops,
+                [
+                    Op::GetLex { index: class },
+                    Op::PushString { value: pattern },
+                    Op::PushString { value: flags },
+                    Op::Construct { num_args: 2 },
+                ],
+                "{}",
+                source
+            );
+        }
+    }
+
+    #[test]
+    fn for_in_var_declares_a_local() {
+        let class = parse_class("", "for (var k: String in obj) {}");
+        let mut context = ConstantPoolContext::default();
+        let mut generator = generate(&class, false, &mut context);
+
+        let register = generator.locals[&"k".to_owned()];
+        let index = generator.context.add_public_qname(&"String".to_owned());
+        let ops = raw_ops(&generator);
+        let next = ops.iter().position(|op| *op == Op::NextName).unwrap();
+        assert_eq!(
+            ops[next + 1..][..2],
+            [Op::Coerce { index }, Op::SetLocal { index: register }]
+        );
+    }
+
+    #[test]
+    fn bare_for_in_assigns_a_declared_local() {
+        let class = parse_class("", "var k: int = 0; for each (k in obj) {}");
+        let mut context = ConstantPoolContext::default();
+        let generator = generate(&class, false, &mut context);
+
+        let register = generator.locals[&"k".to_owned()];
+        let ops = raw_ops(&generator);
+        let next = ops.iter().position(|op| *op == Op::NextValue).unwrap();
+        assert_eq!(
+            ops[next + 1..][..2],
+            [Op::ConvertI, Op::SetLocal { index: register },]
+        );
+    }
+
+    #[test]
+    fn bare_for_in_assigns_an_existing_variable() {
+        let class = parse_class("", "for (k in obj) {}");
+        let mut context = ConstantPoolContext::default();
+        let mut generator = generate(&class, false, &mut context);
+
+        // `k` is not declared in the function, so it is stored on the scope stack.
+        assert!(!generator.is_local(&"k".to_owned()));
+        let index = generator.context.add_public_qname(&"k".to_owned());
+        let ops = raw_ops(&generator);
+        let store = ops
+            .iter()
+            .position(|op| *op == Op::FindProperty { index })
+            .expect("Expected k to be looked up");
+        assert_eq!(
+            ops[store + 1..][..4],
+            [
+                Op::GetLocal { index: 1 },
+                Op::GetLocal { index: 2 },
+                Op::NextName,
+                Op::SetProperty { index },
+            ]
+        );
+    }
+
+    #[test]
+    fn constants_are_initialised() {
+        let class = parse_class(
+            "public const limit: int = 0; const size: int = 0; public var other: int;",
+            "this.limit = 1; size = 2; this.other = 3; other = 4; this.limit += 5;",
+        );
+        let constants = class.members.iter().map(|member| member.constant);
+        assert_eq!(constants.collect::<Vec<_>>(), [true, true, false]);
+
+        let mut context = ConstantPoolContext::default();
+        let mut generator = generate(&class, true, &mut context);
+
+        let ops = raw_ops(&generator);
+        let stores = ops
+            .into_iter()
+            .filter(|op| matches!(op, Op::InitProperty { .. } | Op::SetProperty { .. }))
+            .collect::<Vec<_>>();
+        let [limit, size, other] = ["limit", "size", "other"]
+            .map(|name| generator.context.add_public_qname(&name.to_owned()));
+        assert_eq!(
+            stores,
+            [
+                Op::InitProperty { index: limit },
+                Op::InitProperty { index: size },
+                Op::SetProperty { index: other },
+                Op::SetProperty { index: other },
+                Op::InitProperty { index: limit },
+            ]
+        );
+    }
+
+    #[test]
+    fn constants_are_only_initialised_by_constructors() {
+        let class = parse_class("const size: int = 0;", "size = 1; this.size = 2;");
+        let mut context = ConstantPoolContext::default();
+        let generator = generate(&class, false, &mut context);
+
+        let ops = raw_ops(&generator);
+        assert!(!ops.iter().any(|op| matches!(op, Op::InitProperty { .. })));
+    }
+
+    #[test]
+    fn locals_hide_constants() {
+        let class = parse_class(
+            "const size: int = 0; var limit: int;",
+            "var size = 0; size = 1; this.limit = 2;",
+        );
+        let mut context = ConstantPoolContext::default();
+        let generator = generate(&class, true, &mut context);
+
+        let ops = raw_ops(&generator);
+        assert!(!ops.iter().any(|op| matches!(op, Op::InitProperty { .. })));
+        assert!(ops.iter().any(|op| matches!(op, Op::SetProperty { .. })));
+    }
+
+    #[test]
+    fn new_with_a_package() {
+        let class = parse_class(
+            "",
+            "new flash.display.Sprite(); new mx.core.UIComponent(1); new com.example.Cls();",
+        );
+        let mut context = ConstantPoolContext::default();
+        let mut generator = generate(&class, false, &mut context);
+
+        let sprite = generator
+            .context
+            .add_package_qname(&"flash.display".to_owned(), &"Sprite".to_owned());
+        let component = generator
+            .context
+            .add_package_qname(&"mx.core".to_owned(), &"UIComponent".to_owned());
+        let ops = raw_ops(&generator);
+        assert_eq!(
+            ops[2..4],
+            [
+                Op::FindPropStrict { index: sprite },
+                Op::ConstructProp {
+                    index: sprite,
+                    num_args: 0
+                },
+            ]
+        );
+        assert!(ops.contains(&Op::FindPropStrict { index: component }));
+        assert!(ops.contains(&Op::ConstructProp {
+            index: component,
+            num_args: 1
+        }));
+
+        // Packages outside of the Flash and Flex APIs are found the same way.
+        let cls = generator
+            .context
+            .add_package_qname(&"com.example".to_owned(), &"Cls".to_owned());
+        assert!(ops.contains(&Op::FindPropStrict { index: cls }));
+        assert!(ops.contains(&Op::ConstructProp {
+            index: cls,
+            num_args: 0
+        }));
+    }
+
+    #[test]
+    fn new_with_an_object() {
+        // None of these start with a package, so the class is a property of a object evaluated at runtime.
+        for body in [
+            "new this.factory.Cls();",
+            "new factory.Cls();",
+            "new build.Cls();",
+            "var lib: Object = factory; new lib.Cls();",
+            "new someGlobalObj.Cls();",
+            "new flash.display.Cls.Inner();",
+        ] {
+            let class = parse_class(
+                "var factory: Object; public function build(): Object { return factory; }",
+                body,
+            );
+            let mut context = ConstantPoolContext::default();
+            let mut generator = generate(&class, false, &mut context);
+
+            let name = body.rsplit('.').next().unwrap().trim_end_matches("();");
+            let index = generator.context.add_public_qname(&name.to_owned());
+            let ops = raw_ops(&generator);
+            assert!(
+                ops.contains(&Op::ConstructProp { index, num_args: 0 }),
+                "{}: {:?}",
+                body,
+                ops
+            );
+            assert!(!ops.contains(&Op::FindPropStrict { index }), "{}", body);
+        }
+    }
+
+    #[test]
+    fn package_names() {
+        let class = parse_class("var factory: Object;", "var lib: Object = factory;");
+        let mut context = ConstantPoolContext::default();
+        let mut generator = generate(&class, false, &mut context);
+
+        let package_name = |generator: &FunctionGenerator, source: &str| {
+            let (expression, errors) = crate::parser::as3::parse_expression(source);
+            assert!(errors.is_empty(), "{:?}", errors);
+            generator.package_name(&expression.unwrap())
+        };
+        let cases = [
+            ("flash", Some("flash")),
+            ("flash.display", Some("flash.display")),
+            ("mx.core", Some("mx.core")),
+            ("spark.components", Some("spark.components")),
+            ("com.example.utils", Some("com.example.utils")),
+            ("registry", Some("registry")),
+            ("this", None),
+            ("this.factory", None),
+            ("factory", None),
+            ("lib", None),
+            ("lib.utils", None),
+            ("test", None),
+            ("someGlobalObj", None),
+            ("flash.display.Sprite", None),
+            ("f().display", None),
+        ];
+        for (source, expected) in cases {
+            assert_eq!(
+                package_name(&generator, source).as_deref(),
+                expected,
+                "{}",
+                source
+            );
+        }
+
+        // Inside a nested function the locals of this one are still objects.
+        let nested = generator.nested_generator(LocalResolverVisitor::default());
+        for (source, expected) in cases {
+            assert_eq!(
+                package_name(&nested, source).as_deref(),
+                expected,
+                "{}",
+                source
+            );
+        }
+    }
+
+    /// Generates a method, and returns the scope depths of every method body, nested functions come first.
+    fn scope_depths(body: &str) -> Vec<(u32, u32)> {
+        let class = parse_class("", body);
+        let mut context = ConstantPoolContext::default();
+        generate(&class, false, &mut context);
+
+        context
+            .method_bodies()
+            .iter()
+            .map(|body| (body.init_scope_depth, body.max_scope_depth))
+            .collect()
+    }
+
+    #[test]
+    fn scope_depth() {
+        // Only `this` is pushed.
+        assert_eq!(scope_depths("var x: int = 1;"), [(0, 1)]);
+        // The activation is pushed as well.
+        assert_eq!(
+            scope_depths("var x: int = 1; var f: Object = function(): int { return x; };"),
+            [(2, 3), (0, 2)]
+        );
+        // A function without an activation passes on a shallower scope stack.
+        assert_eq!(
+            scope_depths(
+                "var x: int = 1; var f: Object = function(): Object { return function(): int { return x; }; };"
+            ),
+            [(3, 4), (2, 3), (0, 2)]
+        );
+        // Declared functions are created with the same scope stack.
+        assert_eq!(
+            scope_depths("var x: int = 1; function f(): int { return x; }"),
+            [(2, 3), (0, 2)]
+        );
+    }
+
+    #[test]
+    fn scope_depth_after_catch() {
+        // The scope stack is restored when an exception is caught, not pushed on top of the old one.
+        assert_eq!(
+            scope_depths(
+                "var x: int = 1; try { x = 2; } catch (e: Error) { var f: Object = function(): int { return x; }; }"
+            ),
+            [(2, 3), (0, 2)]
+        );
+    }
+
+    #[test]
+    fn named_function_expression() {
+        let class = parse_class(
+            "",
+            "var f: Object = function g(n: int): int { return g(n); };",
+        );
+        let mut context = ConstantPoolContext::default();
+        let mut generator = generate(&class, false, &mut context);
+
+        // The name is in a scope of its own, which the closure is created in.
+        let g = generator.context.add_public_qname(&"g".to_owned());
+        let ops = raw_ops(&generator);
+        let start = ops
+            .iter()
+            .position(|op| *op == Op::NewObject { num_args: 0 })
+            .unwrap();
+        assert_eq!(
+            ops[start..][..8],
+            [
+                Op::NewObject { num_args: 0 },
+                Op::PushScope,
+                Op::NewFunction {
+                    index: Index::new(0)
+                },
+                Op::Dup,
+                Op::GetScopeObject { index: 1 },
+                Op::Swap,
+                Op::SetProperty { index: g },
+                Op::PopScope,
+            ]
+        );
+        assert!(!generator.is_local(&"g".to_owned()));
+
+        assert_eq!(generator.scope_depth, 1);
+        assert_eq!(generator.max_scope_depth, 2);
+        drop(generator);
+        let depths = context
+            .method_bodies()
+            .iter()
+            .map(|body| (body.init_scope_depth, body.max_scope_depth))
+            .collect::<Vec<_>>();
+        assert_eq!(depths, [(2, 3), (0, 2)]);
+    }
+
+    #[test]
+    fn captured_locals() {
+        let class = parse_class(
+            "",
+            "var x: int = 1; var f: Object = function(): void { x = x + 2; }; x = 3; var y: int = x;",
+        );
+        let mut context = ConstantPoolContext::default();
+        let mut generator = generate(&class, false, &mut context);
+
+        let [x, int] =
+            ["x", "int"].map(|name| generator.context.add_public_qname(&name.to_owned()));
+        let slot = generator.slots[&"x".to_owned()];
+        let activation = generator.activation_register.unwrap();
+        let ops = raw_ops(&generator);
+
+        // The activation is created and pushed right after `this`, and kept in a register as well.
+        assert_eq!(
+            ops[..6],
+            [
+                Op::GetLocal { index: 0 },
+                Op::PushScope,
+                Op::NewActivation,
+                Op::Dup,
+                Op::SetLocal { index: activation },
+                Op::PushScope,
+            ]
+        );
+        // The captured local lives in a slot of the activation, which is the second object on the scope stack.
+        let store = [
+            Op::ConvertI,
+            Op::GetScopeObject { index: 1 },
+            Op::Swap,
+            Op::SetSlot { index: slot },
+        ];
+        assert_eq!(ops[7..11], store);
+        assert_eq!(ops[15..19], store);
+        assert_eq!(
+            ops[19..22],
+            [
+                Op::GetScopeObject { index: 1 },
+                Op::GetSlot { index: slot },
+                Op::ConvertI,
+            ]
+        );
+        // The register is only read to restore the scope stack in exception handlers.
+        assert!(!ops
+            .iter()
+            .any(|op| matches!(op, Op::GetLocal { index } if *index == activation)));
+
+        drop(generator);
+        let [closure, method] = context.method_bodies() else {
+            panic!("Expected the closure and the method");
+        };
+
+        // The slot is a trait of the activation, which is described by the body of the method.
+        let [slot_trait] = &method.traits[..] else {
+            panic!("Expected a single slot, got {:?}", method.traits);
+        };
+        assert_eq!(slot_trait.name, x);
+        assert!(matches!(
+            slot_trait.kind,
+            TraitKind::Slot { slot_id, type_name, value: None } if slot_id == slot && type_name == int
+        ));
+        assert!(closure.traits.is_empty());
+
+        // getscopeobject only reaches the scope stack of the running method, the closure finds the activation on
+        // the scope chain it was created with by the name of the slot: getlocal0, pushscope, findproperty x,
+        // getlex x, pushbyte 2, add, setproperty x and returnvoid.
+        let x = x.index as u8;
+        assert_eq!(
+            closure.code,
+            [0xd0, 0x30, 0x5e, x, 0x60, x, 0x24, 2, 0xa0, 0x61, x, 0x47]
+        );
+    }
+
+    #[test]
+    fn void_values_are_errors() {
+        for body in [
+            "var x: void = 1;",
+            "try {} catch (e: void) {}",
+            "for (var k: void in o) {}",
+            // Conditional compilation is resolved before code generation.
+            "CONFIG::debug { trace(1); }",
+        ] {
+            let class = parse_class("", body);
+            let function = class.functions.last().unwrap();
+            let mut variables = LocalResolverVisitor::default();
+            variables.visit_function(function).unwrap();
+
+            let mut context = ConstantPoolContext::default();
+            let mut generator = FunctionGenerator::new(variables, &mut context);
+            assert!(generator.visit_function(function).is_err(), "{}", body);
+        }
+
+        // A function with a return type can't fall back to `returnvoid`.
+        let (package, diagnostics) = parse_package(
+            "package { public class Test { public function test(): int { return; } } }",
+        );
+        assert!(diagnostics.is_empty(), "{:?}", diagnostics);
+        let class = package.unwrap().classes.remove(0);
+        let function = class.functions.last().unwrap();
+        let mut variables = LocalResolverVisitor::default();
+        variables.visit_function(function).unwrap();
+
+        let mut context = ConstantPoolContext::default();
+        let mut generator = FunctionGenerator::new(variables, &mut context);
+        assert!(generator.visit_function(function).is_err());
+    }
+
+    #[test]
+    fn labeled_break_and_continue() {
+        let class = parse_class(
+            "",
+            "outer: while (a) { while (b) { if (c) continue outer; break outer; } }",
+        );
+        let mut context = ConstantPoolContext::default();
+        let mut generator = generate(&class, false, &mut context);
+
+        let [a, b, c] =
+            ["a", "b", "c"].map(|name| generator.context.add_public_qname(&name.to_owned()));
+        // Loops test their condition at the end, `continue outer` goes to the condition of the outer loop and
+        // `break outer` past its end.
+        assert_eq!(
+            generator.code,
+            [
+                raw(Op::GetLocal { index: 0 }),
+                raw(Op::PushScope),
+                CompilerOp::Jump(LabelIndex(1)),
+                label(0),
+                CompilerOp::Jump(LabelIndex(4)),
+                label(3),
+                raw(Op::GetLex { index: c }),
+                CompilerOp::IfFalse(LabelIndex(6)),
+                CompilerOp::Jump(LabelIndex(1)),
+                label(6),
+                CompilerOp::Jump(LabelIndex(2)),
+                label(4),
+                raw(Op::GetLex { index: b }),
+                CompilerOp::IfTrue(LabelIndex(3)),
+                label(5),
+                label(1),
+                raw(Op::GetLex { index: a }),
+                CompilerOp::IfTrue(LabelIndex(0)),
+                label(2),
+                raw(Op::ReturnVoid),
+            ]
+        );
+
+        // Only the loop bodies are targets of backward branches, so they are the only labels that become a `label`
+        // instruction (0x09).
+        let (bytes, _) = resolver::resolve(&generator.code, &generator.exceptions);
+        let [a, b, c] = [a, b, c].map(|name| name.index as u8);
+        assert_eq!(
+            bytes,
+            [
+                0xd0, 0x30, //
+                0x10, 0x1a, 0, 0, //
+                0x09, 0x10, 0x0f, 0, 0, //
+                0x09, 0x60, c, 0x12, 4, 0, 0, 0x10, 0x0a, 0, 0, 0x10, 0x0c, 0, 0, //
+                0x60, b, 0x11, 0xeb, 0xff, 0xff, //
+                0x60, a, 0x11, 0xe0, 0xff, 0xff, //
+                0x47,
+            ]
+        );
+    }
+
+    #[test]
+    fn switch_without_default() {
+        let class = parse_class("", "switch (x) { case 1: f(); case 2: g(); break; }");
+        let mut context = ConstantPoolContext::default();
+        let mut generator = generate(&class, false, &mut context);
+
+        // The discriminant is kept in register 1 while it is compared to every case.
+        let [x, f, g] =
+            ["x", "f", "g"].map(|name| generator.context.add_public_qname(&name.to_owned()));
+        assert_eq!(
+            generator.code,
+            [
+                raw(Op::GetLocal { index: 0 }),
+                raw(Op::PushScope),
+                raw(Op::GetLex { index: x }),
+                raw(Op::SetLocal { index: 1 }),
+                CompilerOp::Jump(LabelIndex(2)),
+                // The first case falls through into the second one.
+                label(0),
+                raw(Op::FindPropStrict { index: f }),
+                raw(Op::CallPropVoid {
+                    index: f,
+                    num_args: 0
+                }),
+                label(1),
+                raw(Op::FindPropStrict { index: g }),
+                raw(Op::CallPropVoid {
+                    index: g,
+                    num_args: 0
+                }),
+                CompilerOp::Jump(LabelIndex(4)),
+                CompilerOp::Jump(LabelIndex(4)),
+                // Cases are compared with strict equality in order, the first match decides the case index.
+                label(2),
+                raw(Op::PushByte { value: 1 }),
+                raw(Op::GetLocal { index: 1 }),
+                CompilerOp::IfStrictNe(LabelIndex(5)),
+                raw(Op::PushByte { value: 0 }),
+                CompilerOp::Jump(LabelIndex(3)),
+                label(5),
+                raw(Op::PushByte { value: 2 }),
+                raw(Op::GetLocal { index: 1 }),
+                CompilerOp::IfStrictNe(LabelIndex(6)),
+                raw(Op::PushByte { value: 1 }),
+                CompilerOp::Jump(LabelIndex(3)),
+                // Without a default no case matches, the index is out of range and leaves the switch.
+                label(6),
+                raw(Op::PushByte { value: 2 }),
+                label(3),
+                CompilerOp::LookupSwitch {
+                    default: LabelIndex(4),
+                    cases: vec![LabelIndex(0), LabelIndex(1)],
+                },
+                label(4),
+                raw(Op::Kill { index: 1 }),
+                raw(Op::ReturnVoid),
+            ]
+        );
+    }
+
+    #[test]
+    fn switch_with_default_in_the_middle() {
+        let class = parse_class("", "switch (x) { case 1: f(); default: g(); case 2: h(); }");
+        let mut context = ConstantPoolContext::default();
+        let generator = generate(&class, false, &mut context);
+
+        let code = &generator.code;
+        // The bodies keep their order, so the default falls through into the case after it.
+        assert_eq!(
+            code.iter()
+                .filter(|op| matches!(op, CompilerOp::Label(_)))
+                .take(3)
+                .collect::<Vec<_>>(),
+            [&label(0), &label(1), &label(2)]
+        );
+        // The default is only chosen once every case has been compared, by its position among the bodies.
+        assert_eq!(
+            code[code.len() - 10..],
+            [
+                CompilerOp::IfStrictNe(LabelIndex(7)),
+                raw(Op::PushByte { value: 2 }),
+                CompilerOp::Jump(LabelIndex(4)),
+                label(7),
+                raw(Op::PushByte { value: 1 }),
+                label(4),
+                CompilerOp::LookupSwitch {
+                    default: LabelIndex(5),
+                    cases: vec![LabelIndex(0), LabelIndex(1), LabelIndex(2)],
+                },
+                label(5),
+                raw(Op::Kill { index: 1 }),
+                raw(Op::ReturnVoid),
+            ]
+        );
+    }
+
+    /// An exception handler that catches everything, the one that runs a finally block.
+    fn catch_all(from: u32, target: u32) -> CompilerException {
+        CompilerException {
+            from: LabelIndex(from),
+            to: LabelIndex(target),
+            target: LabelIndex(target),
+            variable_name: Index::new(0),
+            type_name: Index::new(0),
+        }
+    }
+
+    #[test]
+    fn try_catch_finally() {
+        let class = parse_class(
+            "",
+            "try { f(); } catch (e: Error) { g(e); } finally { h(); }",
+        );
+        let mut context = ConstantPoolContext::default();
+        let mut generator = generate(&class, false, &mut context);
+
+        // `e` is in register 1, followed by the exception and the exit index of the finally block.
+        let [f, e, error, g, h] = ["f", "e", "Error", "g", "h"]
+            .map(|name| generator.context.add_public_qname(&name.to_owned()));
+        assert_eq!(
+            generator.code,
+            [
+                raw(Op::GetLocal { index: 0 }),
+                raw(Op::PushScope),
+                label(3),
+                raw(Op::FindPropStrict { index: f }),
+                raw(Op::CallPropVoid {
+                    index: f,
+                    num_args: 0
+                }),
+                label(4),
+                // Leaving normally is exit 0.
+                raw(Op::PushByte { value: 0 }),
+                raw(Op::SetLocal { index: 3 }),
+                CompilerOp::Jump(LabelIndex(1)),
+                // The catch clause starts with a fresh scope stack.
+                label(5),
+                raw(Op::GetLocal { index: 0 }),
+                raw(Op::PushScope),
+                raw(Op::Coerce { index: error }),
+                raw(Op::SetLocal { index: 1 }),
+                raw(Op::FindPropStrict { index: g }),
+                raw(Op::GetLocal { index: 1 }),
+                raw(Op::CallPropVoid {
+                    index: g,
+                    num_args: 1
+                }),
+                raw(Op::PushByte { value: 0 }),
+                raw(Op::SetLocal { index: 3 }),
+                CompilerOp::Jump(LabelIndex(1)),
+                // Any other exception is kept for later as exit 1, and falls into the finally block.
+                label(6),
+                raw(Op::GetLocal { index: 0 }),
+                raw(Op::PushScope),
+                raw(Op::SetLocal { index: 2 }),
+                raw(Op::PushByte { value: 1 }),
+                raw(Op::SetLocal { index: 3 }),
+                label(1),
+                raw(Op::FindPropStrict { index: h }),
+                raw(Op::CallPropVoid {
+                    index: h,
+                    num_args: 0
+                }),
+                raw(Op::GetLocal { index: 3 }),
+                CompilerOp::LookupSwitch {
+                    default: LabelIndex(0),
+                    cases: vec![LabelIndex(0), LabelIndex(2)],
+                },
+                label(2),
+                raw(Op::GetLocal { index: 2 }),
+                raw(Op::Throw),
+                label(0),
+                raw(Op::Kill { index: 2 }),
+                raw(Op::Kill { index: 3 }),
+                raw(Op::ReturnVoid),
+            ]
+        );
+        // The catch clause only protects the try block, the finally handler the catch clause as well.
+        assert_eq!(
+            generator.exceptions,
+            [
+                CompilerException {
+                    from: LabelIndex(3),
+                    to: LabelIndex(4),
+                    target: LabelIndex(5),
+                    variable_name: e,
+                    type_name: error,
+                },
+                catch_all(3, 6),
+            ]
+        );
+    }
+
+    #[test]
+    fn return_through_finally() {
+        let (package, diagnostics) = parse_package(
+            "package { public class Test { public function test(): int { \
+             var x: int = 1; try { return x; } finally { h(); } } } }",
+        );
+        assert!(diagnostics.is_empty(), "{:?}", diagnostics);
+        let class = package.unwrap().classes.remove(0);
+        let mut context = ConstantPoolContext::default();
+        let mut generator = generate(&class, false, &mut context);
+
+        // The value waits in the return register 4 while the finally block runs, and is returned by exit 2.
+        let h = generator.context.add_public_qname(&"h".to_owned());
+        assert_eq!(
+            generator.code[5..],
+            [
+                label(3),
+                raw(Op::GetLocal { index: 1 }),
+                raw(Op::ConvertI),
+                raw(Op::SetLocal { index: 4 }),
+                raw(Op::PushByte { value: 2 }),
+                raw(Op::SetLocal { index: 3 }),
+                CompilerOp::Jump(LabelIndex(1)),
+                label(4),
+                raw(Op::PushByte { value: 0 }),
+                raw(Op::SetLocal { index: 3 }),
+                CompilerOp::Jump(LabelIndex(1)),
+                label(6),
+                raw(Op::GetLocal { index: 0 }),
+                raw(Op::PushScope),
+                raw(Op::SetLocal { index: 2 }),
+                raw(Op::PushByte { value: 1 }),
+                raw(Op::SetLocal { index: 3 }),
+                label(1),
+                raw(Op::FindPropStrict { index: h }),
+                raw(Op::CallPropVoid {
+                    index: h,
+                    num_args: 0
+                }),
+                raw(Op::GetLocal { index: 3 }),
+                CompilerOp::LookupSwitch {
+                    default: LabelIndex(0),
+                    cases: vec![LabelIndex(0), LabelIndex(2), LabelIndex(5)],
+                },
+                label(2),
+                raw(Op::GetLocal { index: 2 }),
+                raw(Op::Throw),
+                label(5),
+                raw(Op::GetLocal { index: 4 }),
+                raw(Op::ReturnValue),
+                label(0),
+                raw(Op::Kill { index: 2 }),
+                raw(Op::Kill { index: 3 }),
+                raw(Op::PushUndefined),
+                raw(Op::ConvertI),
+                raw(Op::ReturnValue),
+            ]
+        );
+        assert_eq!(generator.exceptions, [catch_all(3, 6)]);
+    }
+
+    #[test]
+    fn break_out_of_nested_finally() {
+        let class = parse_class(
+            "",
+            "while (true) { try { try { break; } finally { f(); } } finally { g(); } }",
+        );
+        let mut context = ConstantPoolContext::default();
+        let mut generator = generate(&class, false, &mut context);
+
+        let [f, g] = ["f", "g"].map(|name| generator.context.add_public_qname(&name.to_owned()));
+        let code = &generator.code;
+        let contains =
+            |expected: &[CompilerOp]| code.windows(expected.len()).any(|code| code == expected);
+        // The break runs the inner finally block as its exit 2, which continues with exit 2 of the outer one.
+        assert!(contains(&[
+            label(11),
+            raw(Op::PushByte { value: 2 }),
+            raw(Op::SetLocal { index: 5 }),
+            CompilerOp::Jump(LabelIndex(9)),
+        ]));
+        assert!(contains(&[
+            label(9),
+            raw(Op::FindPropStrict { index: f }),
+            raw(Op::CallPropVoid {
+                index: f,
+                num_args: 0
+            }),
+            raw(Op::GetLocal { index: 5 }),
+            CompilerOp::LookupSwitch {
+                default: LabelIndex(8),
+                cases: vec![LabelIndex(8), LabelIndex(10), LabelIndex(13)],
+            },
+        ]));
+        assert!(contains(&[
+            label(13),
+            raw(Op::PushByte { value: 2 }),
+            raw(Op::SetLocal { index: 2 }),
+            CompilerOp::Jump(LabelIndex(4)),
+        ]));
+        // Only the outer finally block leaves the loop.
+        assert!(contains(&[
+            label(4),
+            raw(Op::FindPropStrict { index: g }),
+            raw(Op::CallPropVoid {
+                index: g,
+                num_args: 0
+            }),
+            raw(Op::GetLocal { index: 2 }),
+            CompilerOp::LookupSwitch {
+                default: LabelIndex(3),
+                cases: vec![LabelIndex(3), LabelIndex(5), LabelIndex(15)],
+            },
+        ]));
+        assert!(contains(&[label(15), CompilerOp::Jump(LabelIndex(2))]));
+        assert!(contains(&[label(2), raw(Op::ReturnVoid)]));
+        // The inner handler comes first, so it is the one that catches exceptions from the inner try block.
+        assert_eq!(generator.exceptions, [catch_all(11, 14), catch_all(6, 16)]);
+    }
+
+    #[test]
+    fn logical_operator_values() {
+        for (source, branch) in [
+            ("a && b", CompilerOp::IfFalse(LabelIndex(0))),
+            ("a || b", CompilerOp::IfTrue(LabelIndex(0))),
+        ] {
+            let class = parse_class("", &format!("var r: Object = {source};"));
+            let mut context = ConstantPoolContext::default();
+            let mut generator = generate(&class, false, &mut context);
+
+            // The copy of `a` is the result if it decides it, otherwise it's replaced by `b`.
+            let [a, b] =
+                ["a", "b"].map(|name| generator.context.add_public_qname(&name.to_owned()));
+            assert_eq!(
+                generator.code[2..9],
+                [
+                    raw(Op::GetLex { index: a }),
+                    raw(Op::Dup),
+                    branch,
+                    raw(Op::Pop),
+                    raw(Op::GetLex { index: b }),
+                    label(0),
+                    raw(Op::Coerce {
+                        index: generator.context.add_public_qname(&"Object".to_owned())
+                    }),
+                ],
+                "{}",
+                source
+            );
+            assert_eq!(generator.current_stack, 0);
+            assert_eq!(generator.max_stack, 2);
+        }
+    }
+
+    #[test]
+    fn null_and_super() {
+        let class = parse_class(
+            "",
+            "var o: Object = null; super(1); super.f(2); o = super.g; super.h = 3;",
+        );
+        let mut context = ConstantPoolContext::default();
+        let mut generator = generate(&class, false, &mut context);
+
+        let [f, g, h] =
+            ["f", "g", "h"].map(|name| generator.context.add_public_qname(&name.to_owned()));
+        let ops = raw_ops(&generator);
+        let contains = |expected: &[Op]| ops.windows(expected.len()).any(|ops| ops == expected);
+        assert!(ops.contains(&Op::PushNull));
+        // Everything of the super class is reached through `this`.
+        let this = || Op::GetLocal { index: 0 };
+        assert!(contains(&[
+            this(),
+            Op::PushByte { value: 1 },
+            Op::ConstructSuper { num_args: 1 }
+        ]));
+        assert!(contains(&[
+            this(),
+            Op::PushByte { value: 2 },
+            Op::CallSuperVoid {
+                index: f,
+                num_args: 1
+            }
+        ]));
+        assert!(contains(&[this(), Op::GetSuper { index: g }]));
+        assert!(contains(&[
+            this(),
+            Op::PushByte { value: 3 },
+            Op::SetSuper { index: h }
+        ]));
+    }
+
+    #[test]
+    fn fused_comparison_branches() {
+        let class = parse_class(
+            "",
+            "if (a < b) f(); while (a >= b) {} if (!(a <= b)) f(); var r: Boolean = a > b;",
+        );
+        let mut context = ConstantPoolContext::default();
+        let mut generator = generate(&class, false, &mut context);
+
+        let [a, b] = ["a", "b"].map(|name| generator.context.add_public_qname(&name.to_owned()));
+        let code = &generator.code;
+        let compare = |branch: CompilerOp| {
+            let expected = [
+                raw(Op::GetLex { index: a }),
+                raw(Op::GetLex { index: b }),
+                branch,
+            ];
+            code.windows(3).any(|code| code == expected)
+        };
+        // An if skips its body with the negated branch, which also jumps if either side is NaN.
+        assert!(compare(CompilerOp::IfNlt(LabelIndex(0))));
+        // A loop jumps back to its body if the condition holds.
+        assert!(compare(CompilerOp::IfGe(LabelIndex(1))));
+        // Negating the condition of an if skips the body if the comparison holds.
+        assert!(compare(CompilerOp::IfLe(LabelIndex(4))));
+        // Outside of a condition the result is a value.
+        let value = [
+            raw(Op::GetLex { index: a }),
+            raw(Op::GetLex { index: b }),
+            raw(Op::GreaterThan),
+            raw(Op::ConvertB),
+        ];
+        assert!(code.windows(4).any(|code| code == value));
+        assert!(!code.iter().any(|op| matches!(
+            op,
+            CompilerOp::Raw(Op::LessThan | Op::LessEquals | Op::GreaterEquals)
+        )));
+    }
+
+    #[test]
+    fn increments_and_compound_assignments() {
+        let class = parse_class(
+            "",
+            "var i: int = 0; var n: Number = 0; i++; --i; n++; o.p++; o[k] += 2; o.q -= 1;",
+        );
+        let mut context = ConstantPoolContext::default();
+        let mut generator = generate(&class, false, &mut context);
+
+        let [o, k, p, q] =
+            ["o", "k", "p", "q"].map(|name| generator.context.add_public_qname(&name.to_owned()));
+        let index = generator.context.add_public_multiname_l();
+        assert_eq!(
+            raw_ops(&generator)[8..],
+            [
+                // Discarded updates of int locals use the integer forms, other locals the generic ones.
+                Op::IncLocalI { index: 1 },
+                Op::DecLocalI { index: 1 },
+                Op::IncLocal { index: 2 },
+                // A property is read and written on the same object.
+                Op::GetLex { index: o },
+                Op::Dup,
+                Op::GetProperty { index: p },
+                Op::Increment,
+                Op::SetProperty { index: p },
+                // The computed name is kept in a temporary register, since it is used twice.
+                Op::GetLex { index: o },
+                Op::GetLex { index: k },
+                Op::SetLocal { index: 3 },
+                Op::Dup,
+                Op::GetLocal { index: 3 },
+                Op::GetProperty { index },
+                Op::PushByte { value: 2 },
+                Op::Add,
+                Op::GetLocal { index: 3 },
+                Op::Swap,
+                Op::Kill { index: 3 },
+                Op::SetProperty { index },
+                Op::GetLex { index: o },
+                Op::Dup,
+                Op::GetProperty { index: q },
+                Op::PushByte { value: 1 },
+                Op::Subtract,
+                Op::SetProperty { index: q },
+                Op::ReturnVoid,
+            ]
+        );
+    }
+
+    #[test]
+    fn calls_and_indexing() {
+        let class = parse_class(
+            "",
+            "var v: Object = f(); o.m(2); v = o[k]; o[k] = v; v = o[k](3); f()(4);",
+        );
+        let mut context = ConstantPoolContext::default();
+        let mut generator = generate(&class, false, &mut context);
+
+        let [f, object, o, m, k] = ["f", "Object", "o", "m", "k"]
+            .map(|name| generator.context.add_public_qname(&name.to_owned()));
+        let index = generator.context.add_public_multiname_l();
+        assert_eq!(
+            raw_ops(&generator)[2..],
+            [
+                // Named functions are called on the object they are found on, the result is only kept if it is used.
+                Op::FindPropStrict { index: f },
+                Op::CallProperty {
+                    index: f,
+                    num_args: 0
+                },
+                Op::Coerce { index: object },
+                Op::SetLocal { index: 1 },
+                Op::GetLex { index: o },
+                Op::PushByte { value: 2 },
+                Op::CallPropVoid {
+                    index: m,
+                    num_args: 1
+                },
+                // Computed names are looked up with a runtime multiname in the public namespace.
+                Op::GetLex { index: o },
+                Op::GetLex { index: k },
+                Op::GetProperty { index },
+                Op::Coerce { index: object },
+                Op::SetLocal { index: 1 },
+                Op::GetLex { index: o },
+                Op::GetLex { index: k },
+                Op::GetLocal { index: 1 },
+                Op::SetProperty { index },
+                Op::GetLex { index: o },
+                Op::GetLex { index: k },
+                Op::PushByte { value: 3 },
+                Op::CallProperty { index, num_args: 1 },
+                Op::Coerce { index: object },
+                Op::SetLocal { index: 1 },
+                // Any other value is called as a function, with null as `this`.
+                Op::FindPropStrict { index: f },
+                Op::CallProperty {
+                    index: f,
+                    num_args: 0
+                },
+                Op::PushNull,
+                Op::PushByte { value: 4 },
+                Op::Call { num_args: 1 },
+                Op::Pop,
+                Op::ReturnVoid,
+            ]
+        );
+    }
+
+    #[test]
+    fn ternaries() {
+        let class = parse_class("", "var r: int = a ? 1 : b ? 2 : 3;");
+        let mut context = ConstantPoolContext::default();
+        let mut generator = generate(&class, false, &mut context);
+
+        let [a, b] = ["a", "b"].map(|name| generator.context.add_public_qname(&name.to_owned()));
+        assert_eq!(
+            generator.code[2..17],
+            [
+                raw(Op::GetLex { index: a }),
+                CompilerOp::IfFalse(LabelIndex(0)),
+                raw(Op::PushByte { value: 1 }),
+                CompilerOp::Jump(LabelIndex(1)),
+                label(0),
+                // The nested ternary is the whole else arm.
+                raw(Op::GetLex { index: b }),
+                CompilerOp::IfFalse(LabelIndex(2)),
+                raw(Op::PushByte { value: 2 }),
+                CompilerOp::Jump(LabelIndex(3)),
+                label(2),
+                raw(Op::PushByte { value: 3 }),
+                label(3),
+                label(1),
+                raw(Op::ConvertI),
+                raw(Op::SetLocal { index: 1 }),
+            ]
+        );
+        assert_eq!(generator.current_stack, 0);
+        assert_eq!(generator.max_stack, 1);
+
+        // Only one of the arms leaves its value on the stack.
+        let class = parse_class("", "var r: int = 1 + (a ? 2 : 3) + 4;");
+        let mut context = ConstantPoolContext::default();
+        let generator = generate(&class, false, &mut context);
+        assert_eq!(generator.current_stack, 0);
+        assert_eq!(generator.max_stack, 2);
+    }
+
+    /// Generates a method that assigns a value to a local, and returns the ops that compute the value.
+    fn value_ops(value: &str) -> (Vec<Op>, ConstantPoolContext) {
+        let class = parse_class("", &format!("var r: Object = {value};"));
+        let mut context = ConstantPoolContext::default();
+        let generator = generate(&class, false, &mut context);
+        assert_eq!(generator.current_stack, 0, "{}", value);
+
+        // Leaves out the setup of the scope stack, and the coercion and store into the local.
+        let ops = raw_ops(&generator);
+        let end = ops
+            .iter()
+            .position(|op| matches!(op, Op::SetLocal { .. }))
+            .unwrap();
+        let ops = ops[2..end - 1].to_vec();
+        drop(generator);
+        (ops, context)
+    }
+
+    #[test]
+    fn array_literals() {
+        let (ops, mut context) = value_ops("[1, x, 's']");
+        let x = context.add_public_qname(&"x".to_owned());
+        let s = context.add_string(&"s".to_owned());
+        assert_eq!(
+            ops,
+            [
+                Op::PushByte { value: 1 },
+                Op::GetLex { index: x },
+                Op::PushString { value: s },
+                Op::NewArray { num_args: 3 },
+            ]
+        );
+
+        // Holes are undefined, a trailing comma does not add one.
+        let (ops, _) = value_ops("[1, , 2, ]");
+        assert_eq!(
+            ops,
+            [
+                Op::PushByte { value: 1 },
+                Op::PushUndefined,
+                Op::PushByte { value: 2 },
+                Op::NewArray { num_args: 3 },
+            ]
+        );
+        let (ops, _) = value_ops("[, ]");
+        assert_eq!(ops, [Op::PushUndefined, Op::NewArray { num_args: 1 }]);
+        let (ops, _) = value_ops("[]");
+        assert_eq!(ops, [Op::NewArray { num_args: 0 }]);
+    }
+
+    #[test]
+    fn object_literals() {
+        let (ops, mut context) = value_ops("{a: 1, 'b': x, 3: y}");
+        let [a, b, three] = ["a", "b", "3"].map(|name| context.add_string(&name.to_owned()));
+        let [x, y] = ["x", "y"].map(|name| context.add_public_qname(&name.to_owned()));
+
+        // Every property pushes its name and then its value.
+        assert_eq!(
+            ops,
+            [
+                Op::PushString { value: a },
+                Op::PushByte { value: 1 },
+                Op::PushString { value: b },
+                Op::GetLex { index: x },
+                Op::PushString { value: three },
+                Op::GetLex { index: y },
+                Op::NewObject { num_args: 3 },
+            ]
+        );
+
+        let (ops, _) = value_ops("{}");
+        assert_eq!(ops, [Op::NewObject { num_args: 0 }]);
+    }
 }
@@ -0,0 +1,334 @@
+//! Label resolution, the last step of generating code for a function.
+//!
+//! Branches are generated as [`CompilerOp`]s that refer to a [`crate::codegen::LabelIndex`], since the target is
+//! usually not known yet when the branch is emitted. Once a function is complete, the resolver lays out all
+//! operations and replaces labels with byte offsets. The same is done for the exception handlers of the function.
+
+use crate::codegen::{CompilerException, CompilerOp, LabelIndex};
+use std::collections::{HashMap, HashSet};
+use swf::avm2::{
+    types::{Exception, LookupSwitch, Op},
+    write::Writer,
+};
+
+/// Every branch instruction is an opcode followed by a 24-bit offset.
+const BRANCH_SIZE: usize = 4;
+
+/// Resolves all labels and writes out the bytecode and exception handlers of a function.
+pub fn resolve(code: &[CompilerOp], exceptions: &[CompilerException]) -> (Vec<u8>, Vec<Exception>) {
+    // The verifier requires the target of a backward branch to be a `label` instruction. A branch jumps
+    // backwards if its label appears before it.
+    let mut seen = HashSet::new();
+    let mut backward = HashSet::new();
+    for op in code {
+        if let CompilerOp::Label(label) = op {
+            seen.insert(*label);
+        }
+
+        backward.extend(
+            op.targets()
+                .into_iter()
+                .filter(|label| seen.contains(label)),
+        );
+    }
+
+    // Branches always take up the same amount of bytes, so the position of every label can be found without
+    // knowing any of the offsets.
+    let mut labels = HashMap::new();
+    let mut position = 0;
+    for op in code {
+        if let CompilerOp::Label(label) = op {
+            labels.insert(*label, position);
+        }
+
+        if let Some(op) = lower(op, position, &labels, &backward) {
+            position += op_size(&op);
+        }
+    }
+
+    let exception_labels = exceptions
+        .iter()
+        .flat_map(|exception| [exception.from, exception.to, exception.target]);
+    for label in code
+        .iter()
+        .flat_map(CompilerOp::targets)
+        .chain(exception_labels)
+    {
+        assert!(
+            labels.contains_key(&label),
+            "Label {:?} was never emitted",
+            label
+        );
+    }
+
+    let mut out = Vec::new();
+    for op in code {
+        if let Some(op) = lower(op, out.len(), &labels, &backward) {
+            write_op(&mut out, &op);
+        }
+    }
+
+    let exceptions = exceptions
+        .iter()
+        .map(|exception| Exception {
+            from_offset: labels[&exception.from] as u32,
+            to_offset: labels[&exception.to] as u32,
+            target_offset: labels[&exception.target] as u32,
+            variable_name: exception.variable_name,
+            type_name: exception.type_name,
+        })
+        .collect();
+
+    (out, exceptions)
+}
+
+/// Converts an operation at `position` into a real opcode, if it results in one.
+fn lower(
+    op: &CompilerOp,
+    position: usize,
+    labels: &HashMap<LabelIndex, usize>,
+    backward: &HashSet<LabelIndex>,
+) -> Option<Op> {
+    // Labels that are not laid out yet don't matter, since the offset does not change the size of an instruction.
+    let offset_from = |label: &LabelIndex, from: usize| {
+        labels
+            .get(label)
+            .map_or(0, |target| (*target as i64 - from as i64) as i32)
+    };
+    // Branch offsets are relative to the end of the instruction.
+    let branch = |label: &LabelIndex| offset_from(label, position + BRANCH_SIZE);
+
+    Some(match op {
+        CompilerOp::Raw(op) => op.clone(),
+        CompilerOp::Label(label) => return backward.contains(label).then_some(Op::Label),
+        CompilerOp::IfFalse(label) => Op::IfFalse {
+            offset: branch(label),
+        },
+        CompilerOp::IfTrue(label) => Op::IfTrue {
+            offset: branch(label),
+        },
+        CompilerOp::IfEq(label) => Op::IfEq {
+            offset: branch(label),
+        },
+        CompilerOp::IfNe(label) => Op::IfNe {
+            offset: branch(label),
+        },
+        CompilerOp::IfStrictEq(label) => Op::IfStrictEq {
+            offset: branch(label),
+        },
+        CompilerOp::IfStrictNe(label) => Op::IfStrictNe {
+            offset: branch(label),
+        },
+        CompilerOp::IfLt(label) => Op::IfLt {
+            offset: branch(label),
+        },
+        CompilerOp::IfLe(label) => Op::IfLe {
+            offset: branch(label),
+        },
+        CompilerOp::IfGt(label) => Op::IfGt {
+            offset: branch(label),
+        },
+        CompilerOp::IfGe(label) => Op::IfGe {
+            offset: branch(label),
+        },
+        CompilerOp::IfNlt(label) => Op::IfNlt {
+            offset: branch(label),
+        },
+        CompilerOp::IfNle(label) => Op::IfNle {
+            offset: branch(label),
+        },
+        CompilerOp::IfNgt(label) => Op::IfNgt {
+            offset: branch(label),
+        },
+        CompilerOp::IfNge(label) => Op::IfNge {
+            offset: branch(label),
+        },
+        CompilerOp::Jump(label) => Op::Jump {
+            offset: branch(label),
+        },
+        // Unlike other branches, lookupswitch offsets are relative to the start of the instruction.
+        CompilerOp::LookupSwitch { default, cases } => Op::LookupSwitch(Box::new(LookupSwitch {
+            default_offset: offset_from(default, position),
+            case_offsets: cases
+                .iter()
+                .map(|label| offset_from(label, position))
+                .collect(),
+        })),
+    })
+}
+
+fn write_op(out: &mut Vec<u8>, op: &Op) {
+    Writer::new(out)
+        .write_op(op)
+        .expect("Writing to a Vec should not fail");
+}
+
+fn op_size(op: &Op) -> usize {
+    let mut out = Vec::new();
+    write_op(&mut out, op);
+    out.len()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use swf::avm2::types::Index;
+
+    fn raw(op: Op) -> CompilerOp {
+        CompilerOp::Raw(op)
+    }
+
+    #[test]
+    fn forward_jump() {
+        let end = LabelIndex(0);
+        let code = [
+            CompilerOp::Jump(end),
+            raw(Op::Nop),
+            CompilerOp::Label(end),
+            raw(Op::ReturnVoid),
+        ];
+
+        let (bytes, _) = resolve(&code, &[]);
+
+        // The offset is relative to the end of the jump, and no label instruction is needed.
+        assert_eq!(bytes, [0x10, 1, 0, 0, 0x02, 0x47]);
+    }
+
+    #[test]
+    fn backward_jump() {
+        let start = LabelIndex(0);
+        let code = [
+            CompilerOp::Label(start),
+            raw(Op::Nop),
+            CompilerOp::Jump(start),
+        ];
+
+        let (bytes, _) = resolve(&code, &[]);
+
+        // A label instruction is inserted at the target, the jump ends at 6 and goes back to 0.
+        assert_eq!(bytes, [0x09, 0x02, 0x10, 0xfa, 0xff, 0xff]);
+    }
+
+    #[test]
+    fn label_targeted_from_both_directions() {
+        let label = LabelIndex(0);
+        let code = [
+            CompilerOp::IfTrue(label),
+            CompilerOp::Label(label),
+            raw(Op::Nop),
+            CompilerOp::IfFalse(label),
+        ];
+
+        let (bytes, _) = resolve(&code, &[]);
+
+        // The inserted label instruction is the target of both branches.
+        assert_eq!(bytes, [0x11, 0, 0, 0, 0x09, 0x02, 0x12, 0xfa, 0xff, 0xff]);
+    }
+
+    #[test]
+    fn branch_offsets_skip_other_branches() {
+        let (first, second) = (LabelIndex(0), LabelIndex(1));
+        let code = [
+            CompilerOp::IfTrue(second),
+            CompilerOp::Jump(first),
+            CompilerOp::Jump(second),
+            CompilerOp::Label(first),
+            CompilerOp::Label(second),
+        ];
+
+        let (bytes, _) = resolve(&code, &[]);
+
+        // Every branch takes up four bytes, whether its target is laid out yet or not.
+        assert_eq!(bytes, [0x11, 8, 0, 0, 0x10, 4, 0, 0, 0x10, 0, 0, 0]);
+    }
+
+    #[test]
+    fn lookupswitch_offsets_are_relative_to_the_instruction() {
+        let (first, second, default) = (LabelIndex(0), LabelIndex(1), LabelIndex(2));
+        let code = [
+            raw(Op::PushByte { value: 1 }),
+            CompilerOp::LookupSwitch {
+                default,
+                cases: vec![first, second],
+            },
+            CompilerOp::Label(first),
+            raw(Op::PushByte { value: 10 }),
+            CompilerOp::Jump(default),
+            CompilerOp::Label(second),
+            raw(Op::PushByte { value: 20 }),
+            CompilerOp::Label(default),
+            raw(Op::ReturnVoid),
+        ];
+
+        let (bytes, _) = resolve(&code, &[]);
+
+        // The lookupswitch starts at 2 and is 11 bytes long, so the cases are at 13 and 19 and the default at 21.
+        assert_eq!(
+            bytes,
+            [
+                0x24, 1, //
+                0x1b, 19, 0, 0, 1, 11, 0, 0, 17, 0, 0, //
+                0x24, 10, //
+                0x10, 2, 0, 0, //
+                0x24, 20, //
+                0x47,
+            ]
+        );
+    }
+
+    #[test]
+    fn lookupswitch_to_an_earlier_label() {
+        let (start, default) = (LabelIndex(0), LabelIndex(1));
+        let code = [
+            CompilerOp::Label(start),
+            raw(Op::PushByte { value: 0 }),
+            CompilerOp::LookupSwitch {
+                default,
+                cases: vec![start],
+            },
+            CompilerOp::Label(default),
+        ];
+
+        let (bytes, _) = resolve(&code, &[]);
+
+        assert_eq!(bytes, [0x09, 0x24, 0, 0x1b, 8, 0, 0, 0, 0xfd, 0xff, 0xff]);
+    }
+
+    #[test]
+    fn exception_offsets() {
+        let (from, to, target, end) = (LabelIndex(0), LabelIndex(1), LabelIndex(2), LabelIndex(3));
+        let code = [
+            CompilerOp::Label(from),
+            raw(Op::Nop),
+            CompilerOp::Label(to),
+            CompilerOp::Jump(end),
+            CompilerOp::Label(target),
+            raw(Op::Pop),
+            CompilerOp::Label(end),
+            raw(Op::ReturnVoid),
+        ];
+        let exceptions = [CompilerException {
+            from,
+            to,
+            target,
+            variable_name: Index::new(0),
+            type_name: Index::new(0),
+        }];
+
+        let (bytes, exceptions) = resolve(&code, &exceptions);
+
+        assert_eq!(bytes, [0x02, 0x10, 1, 0, 0, 0x29, 0x47]);
+        let [exception] = &exceptions[..] else {
+            panic!("Expected a single exception, got {:?}", exceptions);
+        };
+        assert_eq!(exception.from_offset, 0);
+        assert_eq!(exception.to_offset, 1);
+        assert_eq!(exception.target_offset, 5);
+    }
+
+    #[test]
+    #[should_panic(expected = "was never emitted")]
+    fn missing_label() {
+        resolve(&[CompilerOp::Jump(LabelIndex(0))], &[]);
+    }
+}
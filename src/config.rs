@@ -0,0 +1,378 @@
+//! Conditional compilation with configuration constants, the same way the Flex compiler does it.
+//!
+//! Constants are defined on the command line, e.g. `-define=CONFIG::debug,true`. Blocks and class members guarded
+//! by a constant are only compiled when it is `true`, and constants used in expressions are replaced by their value.
+
+use crate::ast::{
+    fold::{walk_expression, walk_statement},
+    Class, ClassMember, Expression, Folder, Function, QualifiedName, Statement,
+};
+use crate::diagnostic::Diagnostic;
+use crate::optimizer::AstOptimizer;
+use crate::parser;
+use std::{
+    collections::{HashMap, HashSet},
+    str::FromStr,
+};
+
+/// A configuration constant given on the command line, in the form of `NAMESPACE::NAME,VALUE`.
+#[derive(Clone, Debug)]
+pub struct Define {
+    pub name: QualifiedName,
+    /// Source code of the value, which is parsed as an expression.
+    pub value: String,
+}
+
+impl FromStr for Define {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("expected `NAMESPACE::NAME,VALUE`, got `{}`", s);
+
+        let (name, value) = s.split_once(',').ok_or_else(invalid)?;
+        let (namespace, name) = name.split_once("::").ok_or_else(invalid)?;
+
+        if namespace.is_empty() || name.is_empty() {
+            return Err(invalid());
+        }
+
+        Ok(Self {
+            name: QualifiedName {
+                namespace: namespace.to_owned(),
+                name: name.to_owned(),
+                // The name is not part of any source file.
+                span: 0..0,
+            },
+            value: value.to_owned(),
+        })
+    }
+}
+
+/// A folder that removes disabled code and substitutes configuration constants with their values.
+#[derive(Debug, Default)]
+pub struct ConfigConstants {
+    constants: HashMap<QualifiedName, Expression>,
+    /// Namespaces that contain configuration constants, names in other namespaces are left alone.
+    namespaces: HashSet<String>,
+    errors: Vec<Diagnostic>,
+}
+
+impl ConfigConstants {
+    /// Defines a constant, its value may refer to constants that were defined before it.
+    ///
+    /// Returns the errors found in the value, and a warning if the constant is redefined.
+    pub fn define(&mut self, define: &Define) -> Vec<Diagnostic> {
+        self.namespaces.insert(define.name.namespace.clone());
+
+        let (value, mut diagnostics) = parser::as3::parse_expression(&define.value);
+
+        if let Some(value) = value {
+            let value = self.fold_expression(*value);
+            // Conditions must be a literal, so evaluate the value as far as possible.
+            let value = AstOptimizer::default().fold_expression(value);
+
+            if self.constants.insert(define.name.clone(), value).is_some() {
+                diagnostics.push(
+                    Diagnostic::warning(format!(
+                        "configuration constant `{}` is defined more than once",
+                        define.name
+                    ))
+                    .with_note("the last definition is used"),
+                );
+            }
+        }
+
+        diagnostics.append(&mut self.errors);
+        diagnostics
+    }
+
+    /// Takes the errors found while folding.
+    pub fn take_errors(&mut self) -> Vec<Diagnostic> {
+        std::mem::take(&mut self.errors)
+    }
+
+    /// Checks whether code guarded by `condition` should be compiled.
+    fn is_enabled(&mut self, condition: &QualifiedName) -> bool {
+        match self.constants.get(condition) {
+            Some(Expression::Bool(value)) => *value,
+            Some(_) => {
+                self.errors.push(
+                    Diagnostic::error(format!(
+                        "configuration constant `{}` must be a Boolean to be used as a condition",
+                        condition
+                    ))
+                    .with_primary(condition.span.clone(), "not a Boolean"),
+                );
+                false
+            }
+            None => {
+                self.undefined(condition);
+                false
+            }
+        }
+    }
+
+    /// Reports a configuration constant that was never defined.
+    fn undefined(&mut self, name: &QualifiedName) {
+        self.errors.push(
+            Diagnostic::error(format!("undefined configuration constant `{}`", name))
+                .with_primary(name.span.clone(), "not defined")
+                .with_note(
+                    "configuration constants are defined with `-define=NAMESPACE::NAME,VALUE`",
+                ),
+        );
+    }
+
+    /// Checks whether an optional member condition is met, members without one are always compiled.
+    fn is_member_enabled(&mut self, condition: Option<&QualifiedName>) -> bool {
+        condition.is_none_or(|condition| self.is_enabled(condition))
+    }
+}
+
+impl Folder for ConfigConstants {
+    fn fold_expression(&mut self, v: Expression) -> Expression {
+        match v {
+            Expression::QualifiedName(name) if self.namespaces.contains(&name.namespace) => {
+                match self.constants.get(&name) {
+                    Some(value) => value.clone(),
+                    None => {
+                        self.undefined(&name);
+                        Expression::QualifiedName(name)
+                    }
+                }
+            }
+            x => walk_expression(self, x),
+        }
+    }
+
+    fn fold_statement(&mut self, v: Statement) -> Statement {
+        match v {
+            // Disabled blocks are replaced by an empty one.
+            Statement::Config { condition, block } => {
+                let block = if self.is_enabled(&condition) {
+                    block.into_iter().map(|v| self.fold_statement(v)).collect()
+                } else {
+                    Vec::new()
+                };
+
+                Statement::Block(block)
+            }
+            x => walk_statement(self, x),
+        }
+    }
+
+    fn fold_class(&mut self, v: Class) -> Class {
+        let Class {
+            name,
+            visibility,
+            functions,
+            members,
+        } = v;
+
+        let mut enabled_functions = Vec::new();
+        for function in functions {
+            if self.is_member_enabled(function.condition.as_ref()) {
+                enabled_functions.push(Function {
+                    condition: None,
+                    ..self.fold_function(function)
+                });
+            }
+        }
+
+        let mut enabled_members = Vec::new();
+        for member in members {
+            if self.is_member_enabled(member.condition.as_ref()) {
+                enabled_members.push(ClassMember {
+                    init_expr: member.init_expr.map(|v| Box::new(self.fold_expression(*v))),
+                    condition: None,
+                    ..member
+                });
+            }
+        }
+
+        Class {
+            name,
+            visibility,
+            functions: enabled_functions,
+            members: enabled_members,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ast::Package;
+    use crate::diagnostic::Severity;
+
+    fn constants(defines: &[&str]) -> ConfigConstants {
+        let mut constants = ConfigConstants::default();
+        for define in defines {
+            let errors = constants.define(&define.parse().unwrap());
+            assert!(errors.is_empty(), "{:?}", errors);
+        }
+        constants
+    }
+
+    fn fold(constants: &mut ConfigConstants, source: &str) -> Package {
+        let (package, errors) = parser::as3::parse_package(source);
+        assert!(errors.is_empty(), "{:?}", errors);
+        constants.fold_package(package.unwrap())
+    }
+
+    #[test]
+    fn parse_define() {
+        let define: Define = "CONFIG::debug,true".parse().unwrap();
+        assert_eq!(define.name.namespace, "CONFIG");
+        assert_eq!(define.name.name, "debug");
+        assert_eq!(define.value, "true");
+
+        // Only the first comma separates the name from the value.
+        let define: Define = "CONFIG::text,'a,b'".parse().unwrap();
+        assert_eq!(define.name.name, "text");
+        assert_eq!(define.value, "'a,b'");
+    }
+
+    #[test]
+    fn parse_invalid_define() {
+        for define in [
+            "CONFIG::debug",
+            "debug,true",
+            "::debug,true",
+            "CONFIG::,true",
+            "",
+        ] {
+            assert_eq!(
+                define.parse::<Define>().unwrap_err(),
+                format!("expected `NAMESPACE::NAME,VALUE`, got `{}`", define)
+            );
+        }
+    }
+
+    #[test]
+    fn disabled_members() {
+        let mut constants = constants(&["CONFIG::debug,true", "CONFIG::release,!CONFIG::debug"]);
+        let package = fold(
+            &mut constants,
+            "package { public class A {
+                CONFIG::debug public function log():void {}
+                CONFIG::release public function fast():void {}
+                public function always():void {}
+                CONFIG::debug public var level:int = 1;
+                CONFIG::release public var unused:int = 2;
+            } }",
+        );
+        assert!(constants.take_errors().is_empty());
+
+        let class = &package.classes[0];
+        let functions = class.functions.iter().map(|f| &f.name).collect::<Vec<_>>();
+        assert_eq!(functions, ["log", "always"]);
+        let members = class.members.iter().map(|m| &m.name).collect::<Vec<_>>();
+        assert_eq!(members, ["level"]);
+        assert!(class.functions[0].condition.is_none());
+        assert!(class.members[0].condition.is_none());
+    }
+
+    #[test]
+    fn disabled_blocks() {
+        let mut constants = constants(&["CONFIG::debug,true", "CONFIG::release,false"]);
+        let package = fold(
+            &mut constants,
+            "package { public class A { public function f():void {
+                CONFIG::debug { var a = 1; }
+                CONFIG::release { var b = 2; }
+            } } }",
+        );
+        assert!(constants.take_errors().is_empty());
+
+        let block = &package.classes[0].functions[0].block;
+        assert_eq!(block.len(), 2);
+        assert!(
+            matches!(&block[0], Statement::Block(statements) if statements.len() == 1),
+            "{:?}",
+            block[0]
+        );
+        assert_eq!(block[1], Statement::Block(Vec::new()));
+    }
+
+    #[test]
+    fn substituted_constants() {
+        let mut constants = constants(&["CONFIG::level,2", "CONFIG::double,CONFIG::level * 2"]);
+        let package = fold(
+            &mut constants,
+            "package { public class A { public function f():void { var x = CONFIG::double + other::name; } } }",
+        );
+        assert!(constants.take_errors().is_empty());
+
+        // Names in namespaces without configuration constants are left alone.
+        let Statement::Variable { value, .. } = &package.classes[0].functions[0].block[0] else {
+            panic!("Expected a variable statement");
+        };
+        let Expression::BinaryOperation { lhs, rhs, .. } = &**value else {
+            panic!("Expected a binary operation, got {:?}", value);
+        };
+        assert_eq!(**lhs, Expression::Integer(4));
+        assert!(matches!(&**rhs, Expression::QualifiedName(name) if name.namespace == "other"));
+    }
+
+    #[test]
+    fn redefined_constant() {
+        let mut constants = constants(&["CONFIG::debug,true"]);
+        let define: Define = "CONFIG::debug,false".parse().unwrap();
+        let diagnostics = constants.define(&define);
+
+        let [warning] = &diagnostics[..] else {
+            panic!("Expected a single warning, got {:?}", diagnostics);
+        };
+        assert_eq!(warning.severity, Severity::Warning);
+        assert_eq!(
+            warning.message,
+            "configuration constant `CONFIG::debug` is defined more than once"
+        );
+        assert_eq!(constants.constants[&define.name], Expression::Bool(false));
+    }
+
+    #[test]
+    fn substituted_constants_are_foldable() {
+        let mut constants = constants(&["CONFIG::level,2"]);
+        let package = fold(
+            &mut constants,
+            "package { public class A { public function f():void { var x = CONFIG::level * 3; } } }",
+        );
+        let package = AstOptimizer::default().fold_package(package);
+
+        // The optimizer sees the value of the constant, just like a literal.
+        let Statement::Variable { value, .. } = &package.classes[0].functions[0].block[0] else {
+            panic!("Expected a variable statement");
+        };
+        assert_eq!(**value, Expression::Integer(6));
+    }
+
+    #[test]
+    fn errors_point_at_constant() {
+        let source = "package { public class A { public function f():void {
+            CONFIG::missing { }
+            CONFIG::level { }
+            var x = CONFIG::other;
+        } } }";
+        let mut constants = constants(&["CONFIG::level,2"]);
+        fold(&mut constants, source);
+
+        let errors = constants.take_errors();
+        let labels = errors
+            .iter()
+            .map(|error| {
+                let label = error.primary.as_ref().unwrap();
+                (&source[label.span.clone()], label.message.as_str())
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            labels,
+            [
+                ("CONFIG::missing", "not defined"),
+                ("CONFIG::level", "not a Boolean"),
+                ("CONFIG::other", "not defined")
+            ]
+        );
+    }
+}
@@ -0,0 +1,479 @@
+//! Errors and warnings reported to the user, rendered together with the offending source code.
+
+use crate::lexer::LexicalError;
+use crate::source_map::SourceMap;
+use lalrpop_util::ParseError;
+use std::{fmt, fmt::Write, ops::Range};
+
+/// Terminals of the grammars that describe a class of tokens rather than a literal piece of text.
+const TOKEN_CLASSES: &[&str] = &[
+    "identifier",
+    "int",
+    "number",
+    "string",
+    "regexp",
+    "bool",
+    "visibility",
+];
+
+/// Width used to display a tab character in source snippets.
+const TAB_WIDTH: usize = 4;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Error => write!(f, "error"),
+            Self::Warning => write!(f, "warning"),
+        }
+    }
+}
+
+/// A message attached to a byte range of the source.
+#[derive(Debug)]
+pub struct Label {
+    pub span: Range<usize>,
+    pub message: String,
+}
+
+#[derive(Debug)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub message: String,
+    /// The location that caused this diagnostic, underlined with `^`.
+    pub primary: Option<Label>,
+    /// Related locations, underlined with `-`.
+    pub secondary: Vec<Label>,
+    pub notes: Vec<String>,
+}
+
+impl Diagnostic {
+    pub fn new(severity: Severity, message: impl Into<String>) -> Self {
+        Self {
+            severity,
+            message: message.into(),
+            primary: None,
+            secondary: Vec::new(),
+            notes: Vec::new(),
+        }
+    }
+
+    pub fn error(message: impl Into<String>) -> Self {
+        Self::new(Severity::Error, message)
+    }
+
+    pub fn warning(message: impl Into<String>) -> Self {
+        Self::new(Severity::Warning, message)
+    }
+
+    pub fn with_primary(mut self, span: Range<usize>, message: impl Into<String>) -> Self {
+        self.primary = Some(Label {
+            span,
+            message: message.into(),
+        });
+        self
+    }
+
+    pub fn with_secondary(mut self, span: Range<usize>, message: impl Into<String>) -> Self {
+        self.secondary.push(Label {
+            span,
+            message: message.into(),
+        });
+        self
+    }
+
+    pub fn with_note(mut self, note: impl Into<String>) -> Self {
+        self.notes.push(note.into());
+        self
+    }
+
+    /// Renders the diagnostic in a similar style to rustc, showing the source lines of every label.
+    ///
+    /// If the diagnostic points into an included file, the include directives that lead to it are shown too.
+    pub fn render(&self, source_map: &SourceMap) -> String {
+        let main_label = self.primary.as_ref().or(self.secondary.first());
+        let include_sites = main_label
+            .map(|label| include_sites(source_map, label.span.start))
+            .unwrap_or_default();
+
+        let mut labels = self
+            .primary
+            .iter()
+            .map(|label| (label, '^'))
+            .chain(self.secondary.iter().map(|label| (label, '-')))
+            .collect::<Vec<_>>();
+        labels.sort_by_key(|(label, _)| label.span.start);
+        labels.extend(include_sites.iter().map(|label| (label, '-')));
+
+        // The gutter must be wide enough to fit the biggest line number.
+        let gutter = labels
+            .iter()
+            .map(|(label, _)| source_map.location(label.span.start).line)
+            .max()
+            .map_or(1, |line| line.to_string().len());
+
+        let mut out = format!("{}: {}\n", self.severity, self.message);
+
+        let mut current_file = None;
+        if let Some(label) = main_label {
+            let (file_id, offset) = source_map.lookup(label.span.start);
+            let file = source_map.file(file_id);
+            let _ = writeln!(
+                out,
+                "{:gutter$}--> {}:{}",
+                "",
+                file.name(),
+                file.location(offset)
+            );
+            current_file = Some(file_id);
+        }
+
+        if !labels.is_empty() {
+            let _ = writeln!(out, "{:gutter$} |", "");
+        }
+
+        for (label, marker) in labels {
+            let (file_id, offset) = source_map.lookup(label.span.start);
+            let file = source_map.file(file_id);
+            let start = file.location(offset);
+
+            // Labels in other files get a header of their own.
+            if current_file != Some(file_id) {
+                let _ = writeln!(out, "{:gutter$}::: {}:{}", "", file.name(), start);
+                current_file = Some(file_id);
+            }
+
+            let line = file.line(start.line);
+
+            // Underline up to the end of the first line of the span, but always at least one character.
+            let line_end = file.line_span(start.line).end;
+            let end = (offset + label.span.len()).min(line_end).max(offset);
+            let end_column = file.location(end).column;
+
+            let underline_start = display_width(line.chars().take(start.column - 1));
+            let underline_len = display_width(
+                line.chars()
+                    .skip(start.column - 1)
+                    .take(end_column - start.column),
+            )
+            .max(1);
+
+            let _ = writeln!(out, "{:>gutter$} | {}", start.line, expand_tabs(line));
+            let _ = write!(
+                out,
+                "{:gutter$} | {}{}",
+                "",
+                " ".repeat(underline_start),
+                marker.to_string().repeat(underline_len),
+            );
+            if !label.message.is_empty() {
+                let _ = write!(out, " {}", label.message);
+            }
+            out.push('\n');
+        }
+
+        if !self.notes.is_empty() {
+            let _ = writeln!(out, "{:gutter$} |", "");
+        }
+
+        for note in &self.notes {
+            let _ = writeln!(out, "{:gutter$} = note: {}", "", note);
+        }
+
+        out
+    }
+}
+
+impl From<LexicalError> for Diagnostic {
+    fn from(value: LexicalError) -> Self {
+        match value {
+            LexicalError::InvalidToken { start, end } => {
+                Diagnostic::error("invalid token").with_primary(start..end, "not recognized")
+            }
+            LexicalError::InvalidEscape { start, end } => {
+                Diagnostic::error("invalid escape sequence in string literal")
+                    .with_primary(start..end, "not a valid escape")
+                    .with_note("`\\x` requires 2 and `\\u` requires 4 hexadecimal digits")
+            }
+            LexicalError::UnterminatedRegExp { start, end } => {
+                Diagnostic::error("unterminated regular expression literal")
+                    .with_primary(start..end, "missing closing `/`")
+            }
+        }
+    }
+}
+
+impl<T: fmt::Display> From<ParseError<usize, T, LexicalError>> for Diagnostic {
+    fn from(value: ParseError<usize, T, LexicalError>) -> Self {
+        match value {
+            ParseError::InvalidToken { location } => {
+                Diagnostic::error("invalid token").with_primary(location..location, "")
+            }
+            ParseError::UnrecognizedEof { location, expected } => {
+                Diagnostic::error("unexpected end of file")
+                    .with_primary(location..location, describe_expected(&expected))
+            }
+            ParseError::UnrecognizedToken {
+                token: (start, token, end),
+                expected,
+            } => Diagnostic::error(format!("unexpected {}", token))
+                .with_primary(start..end, describe_expected(&expected)),
+            ParseError::ExtraToken {
+                token: (start, token, end),
+            } => Diagnostic::error(format!("unexpected {}", token))
+                .with_primary(start..end, "expected end of file"),
+            ParseError::User { error } => error.into(),
+        }
+    }
+}
+
+/// Labels pointing at the include directives that lead to the file containing `offset`, innermost first.
+fn include_sites(source_map: &SourceMap, offset: usize) -> Vec<Label> {
+    let mut sites = Vec::new();
+    let (mut file, _) = source_map.lookup(offset);
+
+    while let Some(span) = source_map.file(file).included_from() {
+        file = source_map.lookup(span.start).0;
+        sites.push(Label {
+            span,
+            message: "included here".to_owned(),
+        });
+    }
+
+    sites
+}
+
+/// Turns the list of expected terminals from lalrpop into a readable sentence.
+fn describe_expected(expected: &[String]) -> String {
+    let names = expected
+        .iter()
+        .map(|terminal| {
+            // lalrpop reports terminals as they are written in the grammar, quotes included.
+            let name = terminal.trim_matches('"');
+
+            if TOKEN_CLASSES.contains(&name) {
+                name.to_owned()
+            } else {
+                format!("`{}`", name)
+            }
+        })
+        .collect::<Vec<_>>();
+
+    match names.as_slice() {
+        [] => String::new(),
+        [name] => format!("expected {}", name),
+        [rest @ .., last] => format!("expected one of {} or {}", rest.join(", "), last),
+    }
+}
+
+fn display_width(chars: impl Iterator<Item = char>) -> usize {
+    chars.map(|c| if c == '\t' { TAB_WIDTH } else { 1 }).sum()
+}
+
+fn expand_tabs(line: &str) -> String {
+    line.replace('\t', &" ".repeat(TAB_WIDTH))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn caret_under_span() {
+        let source_map = SourceMap::new("a.as", "var a;\nvar b = 1 +;\n".to_owned());
+        let diagnostic =
+            Diagnostic::error("unexpected `;`").with_primary(19..20, "expected an expression");
+
+        assert_eq!(
+            diagnostic.render(&source_map),
+            "error: unexpected `;`\n \
+             --> a.as:2:13\n  \
+             |\n\
+             2 | var b = 1 +;\n  \
+             |             ^ expected an expression\n"
+        );
+    }
+
+    #[test]
+    fn span_is_cut_at_line_end() {
+        let source_map = SourceMap::new("a.as", "var s = \"abc\ndef\";\n".to_owned());
+        // An empty span still gets a caret, a span over several lines is only underlined on its first line.
+        let empty = Diagnostic::error("empty").with_primary(4..4, "");
+        let multiline = Diagnostic::error("multiline").with_primary(8..17, "string");
+
+        assert_eq!(
+            empty.render(&source_map),
+            "error: empty\n \
+             --> a.as:1:5\n  \
+             |\n\
+             1 | var s = \"abc\n  \
+             |     ^\n"
+        );
+        assert_eq!(
+            multiline.render(&source_map),
+            "error: multiline\n \
+             --> a.as:1:9\n  \
+             |\n\
+             1 | var s = \"abc\n  \
+             |         ^^^^ string\n"
+        );
+    }
+
+    #[test]
+    fn secondary_labels() {
+        let source_map = SourceMap::new("a.as", "if (a) {\n    f(1;\n}\n".to_owned());
+        let diagnostic = Diagnostic::error("unexpected `;`")
+            .with_primary(16..17, "expected `)`")
+            .with_secondary(6..8, "")
+            .with_secondary(14..15, "unclosed delimiter");
+
+        // Labels are shown in the order of the source, only the primary one is underlined with `^`.
+        assert_eq!(
+            diagnostic.render(&source_map),
+            "error: unexpected `;`\n \
+             --> a.as:2:8\n  \
+             |\n\
+             1 | if (a) {\n  \
+             |       --\n\
+             2 |     f(1;\n  \
+             |      - unclosed delimiter\n\
+             2 |     f(1;\n  \
+             |        ^ expected `)`\n"
+        );
+    }
+
+    #[test]
+    fn secondary_label_without_primary() {
+        let source_map = SourceMap::new("a.as", "var a;\n".to_owned());
+        let diagnostic =
+            Diagnostic::warning("unused variable").with_secondary(4..5, "declared here");
+
+        // The first secondary label decides the location of the diagnostic.
+        assert_eq!(
+            diagnostic.render(&source_map),
+            "warning: unused variable\n \
+             --> a.as:1:5\n  \
+             |\n\
+             1 | var a;\n  \
+             |     - declared here\n"
+        );
+    }
+
+    #[test]
+    fn tabs_are_expanded() {
+        let source_map = SourceMap::new("a.as", "\tx =\t@;\n".to_owned());
+        let diagnostic = Diagnostic::error("invalid token").with_primary(5..6, "not recognized");
+
+        // The column counts a tab as one character, the underline lines up with the expanded tabs.
+        assert_eq!(
+            diagnostic.render(&source_map),
+            "error: invalid token\n \
+             --> a.as:1:6\n  \
+             |\n\
+             1 |     x =    @;\n  \
+             |            ^ not recognized\n"
+        );
+    }
+
+    #[test]
+    fn notes_and_wide_gutter() {
+        let source = format!("{}var x = y;\n", "\n".repeat(11));
+        let source_map = SourceMap::new("a.as", source);
+        let diagnostic = Diagnostic::error("undefined `y`")
+            .with_primary(19..20, "not defined")
+            .with_note("first note")
+            .with_note("second note");
+
+        assert_eq!(
+            diagnostic.render(&source_map),
+            "error: undefined `y`\n  \
+              --> a.as:12:9\n   \
+              |\n\
+             12 | var x = y;\n   \
+              |         ^ not defined\n   \
+              |\n   \
+              = note: first note\n   \
+              = note: second note\n"
+        );
+    }
+
+    #[test]
+    fn without_label() {
+        let source_map = SourceMap::new("a.as", String::new());
+        let diagnostic = Diagnostic::error("no input").with_note("nothing to compile");
+
+        assert_eq!(
+            diagnostic.render(&source_map),
+            "error: no input\n  |\n  = note: nothing to compile\n"
+        );
+    }
+
+    /// `main.as` includes `b.as` on its second line, which includes `c.as` on its first line.
+    fn included() -> SourceMap {
+        let main = "var a;\ninclude \"b.as\";\nvar d;\n";
+        let b = "include \"c.as\"\nvar b;\n";
+        let c = "var c = @;\n";
+
+        let mut source_map = SourceMap::default();
+        let main_id = source_map.add_file("main.as", main.to_owned(), None);
+        source_map.append(main_id, 0..7);
+        let main_site = source_map.append_blank(main_id, 7..22);
+
+        let b_id = source_map.add_file("b.as", b.to_owned(), Some(main_site));
+        let b_site = source_map.append_blank(b_id, 0..14);
+
+        let c_id = source_map.add_file("c.as", c.to_owned(), Some(b_site));
+        source_map.append(c_id, 0..c.len());
+
+        source_map.append_separator(b_id, 14);
+        source_map.append(b_id, 14..b.len());
+        source_map.append_separator(main_id, 22);
+        source_map.append(main_id, 22..main.len());
+        source_map
+    }
+
+    #[test]
+    fn include_sites_in_other_files() {
+        let source_map = included();
+        let offset = source_map.source().find('@').unwrap();
+        let diagnostic =
+            Diagnostic::error("invalid token").with_primary(offset..offset + 1, "not recognized");
+
+        // Every file gets a header of its own, the include directives are underlined with `-`.
+        assert_eq!(
+            diagnostic.render(&source_map),
+            "error: invalid token\n \
+             --> c.as:1:9\n  \
+             |\n\
+             1 | var c = @;\n  \
+             |         ^ not recognized\n \
+             ::: b.as:1:1\n\
+             1 | include \"c.as\"\n  \
+             | -------------- included here\n \
+             ::: main.as:2:1\n\
+             2 | include \"b.as\";\n  \
+             | --------------- included here\n"
+        );
+    }
+
+    #[test]
+    fn labels_in_the_main_file_have_no_include_sites() {
+        let source_map = included();
+        let offset = source_map.source().find("d;").unwrap();
+        let diagnostic = Diagnostic::error("undefined `d`").with_primary(offset..offset + 1, "");
+
+        assert_eq!(
+            diagnostic.render(&source_map),
+            "error: undefined `d`\n \
+             --> main.as:3:5\n  \
+             |\n\
+             3 | var d;\n  \
+             |     ^\n"
+        );
+    }
+}
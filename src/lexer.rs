@@ -1,44 +1,104 @@
 use logos::{Logos, SpannedIter};
+use std::ops::Range;
 
 mod token;
 
-pub use token::{as3, asm};
+pub use token::{as3, asm, NumericLiteral, RegExpLiteral, TokenError};
 
 pub type Spanned<Tok, Loc, Error> = Result<(Loc, Tok, Loc), Error>;
 
 #[derive(Debug)]
 pub enum LexicalError {
-    InvalidToken { line: usize, column: usize },
+    InvalidToken { start: usize, end: usize },
+    InvalidEscape { start: usize, end: usize },
+    UnterminatedRegExp { start: usize, end: usize },
 }
 
-pub struct Lexer<'input, T: Logos<'input, Source = str, Extras = (usize, usize)>> {
+impl LexicalError {
+    /// Byte range of the source that caused the error.
+    pub fn span(&self) -> Range<usize> {
+        match self {
+            Self::InvalidToken { start, end }
+            | Self::InvalidEscape { start, end }
+            | Self::UnterminatedRegExp { start, end } => *start..*end,
+        }
+    }
+
+    fn from_token_error(error: TokenError, span: Range<usize>) -> Self {
+        match error {
+            TokenError::InvalidToken => Self::InvalidToken {
+                start: span.start,
+                end: span.end,
+            },
+            TokenError::InvalidEscape { start, end } => Self::InvalidEscape { start, end },
+            TokenError::UnterminatedRegExp { start, end } => {
+                Self::UnterminatedRegExp { start, end }
+            }
+        }
+    }
+}
+
+/// A token that may have to be lexed differently depending on the token before it.
+pub trait ContextualToken<'input>: Logos<'input, Source = str, Error = TokenError> + Clone {
+    /// Gives a token lexed at `start` the chance to be replaced, based on the `previous` token.
+    ///
+    /// Returns the replacement and its length in bytes, which may extend past the original token.
+    fn relex(
+        &self,
+        _previous: Option<&Self>,
+        _source: &'input str,
+        _start: usize,
+    ) -> Option<Result<(Self, usize), TokenError>> {
+        None
+    }
+}
+
+/// Wraps a logos lexer and yields tokens together with their byte offsets.
+///
+/// Offsets can be turned into line/column pairs with a [`crate::source_map::SourceMap`].
+pub struct Lexer<'input, T: ContextualToken<'input>> {
+    source: &'input str,
     token_stream: SpannedIter<'input, T>,
+    previous: Option<T>,
 }
 
-impl<'input, T: Logos<'input, Source = str, Extras = (usize, usize)>> Lexer<'input, T> {
+impl<'input, T: ContextualToken<'input>> Lexer<'input, T>
+where
+    T::Extras: Default,
+{
     pub fn new(input: &'input str) -> Self {
         Self {
+            source: input,
             token_stream: T::lexer(input).spanned(),
+            previous: None,
         }
     }
 }
 
-impl<'input, T: Logos<'input, Source = str, Extras = (usize, usize)>> Iterator
-    for Lexer<'input, T>
-{
+impl<'input, T: ContextualToken<'input>> Iterator for Lexer<'input, T> {
     type Item = Spanned<T, usize, LexicalError>;
 
     fn next(&mut self) -> Option<Self::Item> {
-        self.token_stream.next().map(|(token, span)| match token {
-            Err(_) => Err(LexicalError::InvalidToken {
-                line: self.token_stream.extras.0,
-                column: span.start - self.token_stream.extras.1,
-            }),
-            Ok(token) => Ok((
-                self.token_stream.extras.0,
-                token,
-                span.start - self.token_stream.extras.1,
-            )),
+        let (token, span) = self.token_stream.next()?;
+
+        let token = token.and_then(|token| {
+            match token.relex(self.previous.as_ref(), self.source, span.start) {
+                Some(Ok((token, len))) => {
+                    // Skip over the part of the input that the replacement covers.
+                    self.token_stream.bump(span.start + len - span.end);
+                    Ok((span.start, token, span.start + len))
+                }
+                Some(Err(e)) => Err(e),
+                None => Ok((span.start, token, span.end)),
+            }
+        });
+
+        Some(match token {
+            Ok(token) => {
+                self.previous = Some(token.1.clone());
+                Ok(token)
+            }
+            Err(e) => Err(LexicalError::from_token_error(e, span)),
         })
     }
 }
@@ -1,23 +1,262 @@
-use logos::{Lexer, Logos, Skip};
+use logos::{Lexer, Logos};
+use std::{fmt, iter::Peekable, str::CharIndices};
 
-pub fn string_literal<'a, T: Logos<'a, Source = str>>(lex: &mut Lexer<'a, T>) -> Option<String> {
+/// A numeric literal, typed the same way asc types it.
+///
+/// Integers that fit into an `int` stay as `int`, bigger ones become `uint` and anything beyond that is a `Number`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum NumericLiteral {
+    Int(i32),
+    Uint(u32),
+    Number(f64),
+}
+
+impl NumericLiteral {
+    fn from_integer(value: u64) -> Self {
+        if let Ok(value) = i32::try_from(value) {
+            Self::Int(value)
+        } else if let Ok(value) = u32::try_from(value) {
+            Self::Uint(value)
+        } else {
+            Self::Number(value as f64)
+        }
+    }
+}
+
+impl fmt::Display for NumericLiteral {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Int(value) => write!(f, "{}", value),
+            Self::Uint(value) => write!(f, "{}", value),
+            Self::Number(value) => write!(f, "{}", value),
+        }
+    }
+}
+
+pub fn decimal_literal<'a, T: Logos<'a, Source = str>>(
+    lex: &mut Lexer<'a, T>,
+) -> Option<NumericLiteral> {
     let slice = lex.slice();
 
-    // Remove double quotes. (left from parsing)
-    slice[1..slice.len() - 1].parse().ok()
+    match slice.parse::<u64>() {
+        Ok(value) => Some(NumericLiteral::from_integer(value)),
+        // Too big even for u64, so it can only be represented as a Number.
+        Err(_) => slice.parse().ok().map(NumericLiteral::Number),
+    }
 }
 
-/// Update the line count and the char index.
-fn newline_callback<'a, T: Logos<'a, Source = str, Extras = (usize, usize)>>(
+pub fn hex_literal<'a, T: Logos<'a, Source = str>>(
     lex: &mut Lexer<'a, T>,
-) -> Skip {
-    lex.extras.0 += 1;
-    lex.extras.1 = lex.span().end;
-    Skip
+) -> Option<NumericLiteral> {
+    // Remove the 0x prefix.
+    let digits = &lex.slice()[2..];
+
+    match u64::from_str_radix(digits, 16) {
+        Ok(value) => Some(NumericLiteral::from_integer(value)),
+        Err(_) => Some(NumericLiteral::Number(
+            digits.chars().fold(0.0, |acc, c| {
+                acc * 16.0 + c.to_digit(16).expect("Regex only allows hex digits") as f64
+            }),
+        )),
+    }
+}
+
+pub fn float_literal<'a, T: Logos<'a, Source = str>>(
+    lex: &mut Lexer<'a, T>,
+) -> Option<NumericLiteral> {
+    lex.slice().parse().ok().map(NumericLiteral::Number)
+}
+
+/// Errors produced while lexing a single token.
+///
+/// These are converted into a [`crate::lexer::LexicalError`] by the lexer, which knows the span of the token.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub enum TokenError {
+    /// The input did not match any token.
+    #[default]
+    InvalidToken,
+    /// A malformed escape sequence in a string literal, with the byte range of the sequence.
+    InvalidEscape { start: usize, end: usize },
+    /// A regular expression literal without a closing slash, spanning up to the end of the line.
+    UnterminatedRegExp { start: usize, end: usize },
+}
+
+pub fn string_literal<'a, T: Logos<'a, Source = str>>(
+    lex: &mut Lexer<'a, T>,
+) -> Result<String, TokenError> {
+    let slice = lex.slice();
+
+    // Remove quotes. (left from parsing)
+    unescape(&slice[1..slice.len() - 1], lex.span().start + 1)
+}
+
+/// Processes ECMAScript escape sequences of a string literal body, `offset` is the position of the body in the source.
+fn unescape(body: &str, offset: usize) -> Result<String, TokenError> {
+    let mut out = String::with_capacity(body.len());
+    let mut chars = body.char_indices().peekable();
+
+    while let Some((start, c)) = chars.next() {
+        if c != '\\' {
+            out.push(c);
+            continue;
+        }
+
+        let Some((_, escape)) = chars.next() else {
+            // The regex guarantees a character after every backslash.
+            unreachable!("Unterminated escape sequence");
+        };
+
+        match escape {
+            'n' => out.push('\n'),
+            'r' => out.push('\r'),
+            't' => out.push('\t'),
+            'b' => out.push('\u{8}'),
+            'f' => out.push('\u{c}'),
+            'v' => out.push('\u{b}'),
+            '0' if !chars.peek().is_some_and(|(_, c)| c.is_ascii_digit()) => out.push('\0'),
+            // Line continuations do not add anything to the string.
+            '\r' => {
+                chars.next_if(|(_, c)| *c == '\n');
+            }
+            '\n' | '\u{2028}' | '\u{2029}' => {}
+            'x' => {
+                let value = hex_escape(&mut chars, 2, body, start, offset)?;
+                out.push(char::from_u32(value).expect("Two hex digits are always a valid char"));
+            }
+            'u' => {
+                let mut value = hex_escape(&mut chars, 4, body, start, offset)?;
+
+                // Characters outside the BMP are written as a pair of surrogate escapes.
+                if (0xD800..0xDC00).contains(&value) {
+                    if let Some(low) = low_surrogate(&body[next_position(&mut chars, body)..]) {
+                        // Skip over the `\uXXXX` of the low surrogate.
+                        for _ in 0..6 {
+                            chars.next();
+                        }
+
+                        value = 0x10000 + ((value - 0xD800) << 10) + (low - 0xDC00);
+                    }
+                }
+
+                // ECMAScript allows lone surrogates, but they can't be stored in UTF-8, so they are replaced the
+                // same way invalid UTF-16 is when it is converted.
+                out.push(char::from_u32(value).unwrap_or(char::REPLACEMENT_CHARACTER));
+            }
+            // Every other character is escaped to itself (e.g. \\, \' and \").
+            c => out.push(c),
+        }
+    }
+
+    Ok(out)
+}
+
+/// Reads exactly `digits` hex digits of an escape sequence starting at `start`.
+fn hex_escape(
+    chars: &mut Peekable<CharIndices>,
+    digits: usize,
+    body: &str,
+    start: usize,
+    offset: usize,
+) -> Result<u32, TokenError> {
+    let mut value = 0;
+
+    for _ in 0..digits {
+        match chars.next_if(|(_, c)| c.is_ascii_hexdigit()) {
+            Some((_, c)) => value = value * 16 + c.to_digit(16).unwrap(),
+            None => return Err(escape_error(chars, body, start, offset)),
+        }
+    }
+
+    Ok(value)
+}
+
+/// Parses a `\uXXXX` escape of a low surrogate at the start of `rest`, which completes a surrogate pair.
+fn low_surrogate(rest: &str) -> Option<u32> {
+    let digits = rest.strip_prefix("\\u")?.get(..4)?;
+    if !digits.chars().all(|c| c.is_ascii_hexdigit()) {
+        return None;
+    }
+
+    let value = u32::from_str_radix(digits, 16).ok()?;
+    (0xDC00..0xE000).contains(&value).then_some(value)
+}
+
+/// Creates an error spanning from the start of an escape sequence up to the next unconsumed character.
+fn escape_error(
+    chars: &mut Peekable<CharIndices>,
+    body: &str,
+    start: usize,
+    offset: usize,
+) -> TokenError {
+    TokenError::InvalidEscape {
+        start: offset + start,
+        end: offset + next_position(chars, body),
+    }
+}
+
+/// Position of the next unconsumed character in the string literal body.
+fn next_position(chars: &mut Peekable<CharIndices>, body: &str) -> usize {
+    chars.peek().map_or(body.len(), |(i, _)| *i)
+}
+
+/// A regular expression literal, e.g. `/ab+c/gi`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct RegExpLiteral {
+    pub pattern: String,
+    pub flags: String,
+}
+
+/// Lexes a regular expression literal starting with the slash at `start`.
+///
+/// Returns the literal and its length in bytes.
+fn regexp_literal(source: &str, start: usize) -> Result<(RegExpLiteral, usize), TokenError> {
+    let remainder = &source[start..];
+    let mut chars = remainder.char_indices().skip(1);
+    let mut in_class = false;
+
+    let pattern_end = loop {
+        match chars.next() {
+            // A slash inside a character class does not end the literal.
+            Some((i, '/')) if !in_class => break Some(i),
+            Some((_, '[')) => in_class = true,
+            Some((_, ']')) => in_class = false,
+            Some((_, '\\')) => match chars.next() {
+                Some((_, '\n' | '\r' | '\u{2028}' | '\u{2029}')) | None => break None,
+                Some(_) => {}
+            },
+            Some((_, '\n' | '\r' | '\u{2028}' | '\u{2029}')) | None => break None,
+            Some(_) => {}
+        }
+    };
+
+    let Some(pattern_end) = pattern_end else {
+        let line_end = remainder
+            .find(['\n', '\r', '\u{2028}', '\u{2029}'])
+            .unwrap_or(remainder.len());
+
+        return Err(TokenError::UnterminatedRegExp {
+            start,
+            end: start + line_end,
+        });
+    };
+
+    let flags_len = remainder[pattern_end + 1..]
+        .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_' || c == '$'))
+        .unwrap_or(remainder.len() - pattern_end - 1);
+    let end = pattern_end + 1 + flags_len;
+
+    Ok((
+        RegExpLiteral {
+            pattern: remainder[1..pattern_end].to_owned(),
+            flags: remainder[pattern_end + 1..end].to_owned(),
+        },
+        end,
+    ))
 }
 
 pub mod asm {
-    use super::{newline_callback, string_literal};
+    use super::{string_literal, TokenError};
+    use crate::lexer::ContextualToken;
     use logos::{Lexer, Logos};
     use std::fmt;
 
@@ -35,10 +274,8 @@ pub mod asm {
     #[logos(skip r"//[^\n]*")]
     // Block comments.
     #[logos(skip r"/\*(?:[^*]|\*[^/])*\*/")]
-    #[logos(skip " ")] // White space
-    #[logos(skip "	")] // Tab
-    // Store current line and column for easier debugging.
-    #[logos(extras = (usize, usize))]
+    #[logos(skip r"[ \t\r\n\f]+")] // White space
+    #[logos(error = TokenError)]
     pub enum Token {
         #[token("function")]
         KeywordFunction,
@@ -111,7 +348,8 @@ pub mod asm {
         Identifier(String),
         #[regex("\\d+", |lex| lex.slice().parse().ok())]
         Integer(i32),
-        #[regex(r#""(?:[^"]|\\")*""#, callback = string_literal)]
+        #[regex(r#""(?:[^"\\\r\n]|\\(?:\r\n|.|\n))*""#, callback = string_literal)]
+        #[regex(r#"'(?:[^'\\\r\n]|\\(?:\r\n|.|\n))*'"#, callback = string_literal)]
         String(String),
 
         #[token("(")]
@@ -126,35 +364,75 @@ pub mod asm {
         Comma,
         #[token(":")]
         Colon,
-
-        #[regex(r"\n", newline_callback)]
-        Newline,
     }
 
+    impl ContextualToken<'_> for Token {}
+
     impl fmt::Display for Token {
         fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-            write!(f, "{:?}", self)
+            let text = match self {
+                Token::KeywordFunction => "function",
+                Token::OpReturnValue => "returnvalue",
+                Token::OpReturnVoid => "returnvoid",
+                Token::OpIfTrue => "iftrue",
+                Token::OpIfFalse => "iffalse",
+                Token::OpIfEq => "ifeq",
+                Token::OpGetLocal(index) => return write!(f, "`getlocal{}`", index),
+                Token::OpSetLocal(index) => return write!(f, "`setlocal{}`", index),
+                Token::OpPushString => "pushstring",
+                Token::OpPushFalse => "pushfalse",
+                Token::OpPushNull => "pushnull",
+                Token::OpPushNamespace => "pushnamespace",
+                Token::OpPushScope => "pushscope",
+                Token::OpPop => "pop",
+                Token::OpSwap => "swap",
+                Token::OpCoerceA => "coerce_a",
+                Token::OpCoerceS => "coerce_s",
+                Token::OpGetGlobalScope => "getglobalscope",
+                Token::OpGetSlot => "getslot",
+                Token::OpSetSlot => "setslot",
+                Token::OpFindProperty => "findproperty",
+                Token::OpFindPropStrict => "findpropstrict",
+                Token::OpCallProperty => "callproperty",
+                Token::OpCallPropVoid => "callpropvoid",
+                Token::OpCall => "call",
+                Token::OpNewFunction => "newfunction",
+                Token::IdFunction => ".function_id",
+                Token::Identifier(name) => return write!(f, "identifier `{}`", name),
+                Token::Integer(value) => return write!(f, "integer `{}`", value),
+                Token::String(value) => return write!(f, "string {:?}", value),
+                Token::LParen => "(",
+                Token::RParen => ")",
+                Token::LCurlyBracket => "{",
+                Token::RCurlyBracket => "}",
+                Token::Comma => ",",
+                Token::Colon => ":",
+            };
+
+            write!(f, "`{}`", text)
         }
     }
 }
 
 pub mod as3 {
-    use super::{newline_callback, string_literal};
+    use super::{
+        decimal_literal, float_literal, hex_literal, regexp_literal, string_literal,
+        NumericLiteral, RegExpLiteral, TokenError,
+    };
     use crate::ast::Visibility;
+    use crate::lexer::ContextualToken;
     use logos::Logos;
     use std::fmt;
 
     /// ActionScript 3 token.
     // NOTE: Useful regexes https://github.com/maciejhirsz/logos/issues/133
     #[derive(Clone, Debug, Logos)]
-    // FIXME: Comments currently do not count towards line numbers.
     // Slash comments.
     #[logos(skip r"//[^\n]*")]
     // Block comments.
     #[logos(skip r"/\*(?:[^*]|\*[^/])*\*/")]
-    #[logos(skip " ")]
-    // Store current line and column for easier debugging.
-    #[logos(extras = (usize, usize))]
+    #[logos(skip r"[ \t\r\n\f]+")] // White space
+    #[logos(error = TokenError)]
     pub enum Token {
         #[token("var")]
         KeywordVar,
@@ -164,17 +442,111 @@ pub mod as3 {
         KeywordClass,
         #[token("function")]
         KeywordFunction,
+        #[token("as")]
+        KeywordAs,
+        #[token("break")]
+        KeywordBreak,
+        #[token("case")]
+        KeywordCase,
+        #[token("catch")]
+        KeywordCatch,
+        #[token("const")]
+        KeywordConst,
+        #[token("continue")]
+        KeywordContinue,
+        #[token("default")]
+        KeywordDefault,
+        #[token("delete")]
+        KeywordDelete,
+        #[token("do")]
+        KeywordDo,
+        #[token("else")]
+        KeywordElse,
+        #[token("extends")]
+        KeywordExtends,
+        #[token("finally")]
+        KeywordFinally,
+        #[token("for")]
+        KeywordFor,
+        #[token("if")]
+        KeywordIf,
+        #[token("implements")]
+        KeywordImplements,
+        #[token("import")]
+        KeywordImport,
+        #[token("in")]
+        KeywordIn,
+        #[token("instanceof")]
+        KeywordInstanceof,
+        #[token("interface")]
+        KeywordInterface,
+        #[token("internal")]
+        KeywordInternal,
+        #[token("is")]
+        KeywordIs,
+        #[token("new")]
+        KeywordNew,
+        #[token("null")]
+        KeywordNull,
+        #[token("return")]
+        KeywordReturn,
+        #[token("super")]
+        KeywordSuper,
+        #[token("switch")]
+        KeywordSwitch,
+        #[token("this")]
+        KeywordThis,
+        #[token("throw")]
+        KeywordThrow,
+        #[token("try")]
+        KeywordTry,
+        #[token("typeof")]
+        KeywordTypeof,
+        #[token("use")]
+        KeywordUse,
+        #[token("void")]
+        KeywordVoid,
+        #[token("while")]
+        KeywordWhile,
+        #[token("with")]
+        KeywordWith,
+
+        // Contextual keywords, these are only keywords in some places and identifiers everywhere else.
+        #[token("each")]
+        KeywordEach,
+        #[token("get")]
+        KeywordGet,
+        #[token("set")]
+        KeywordSet,
+        #[token("namespace")]
+        KeywordNamespace,
+        #[token("include")]
+        KeywordInclude,
+        #[token("dynamic")]
+        KeywordDynamic,
+        #[token("final")]
+        KeywordFinal,
+        #[token("native")]
+        KeywordNative,
+        #[token("override")]
+        KeywordOverride,
+        #[token("static")]
+        KeywordStatic,
 
         #[regex("(public|protected|private)", callback = |lex| lex.slice().parse().ok())]
         KeywordVisibility(Visibility),
 
         #[regex("(true|false)", callback = |lex| lex.slice().parse().ok())]
         Bool(bool),
-        #[regex("[_a-zA-Z][_0-9a-zA-Z]*", priority = 2, callback = |lex| lex.slice().parse().ok())]
+        #[regex("[_$a-zA-Z][_$0-9a-zA-Z]*", priority = 2, callback = |lex| lex.slice().parse().ok())]
         Identifier(String),
-        #[regex("\\d+", |lex| lex.slice().parse().ok())]
-        Integer(i32),
-        #[regex(r#""(?:[^"]|\\")*""#, callback = string_literal)]
+        #[regex("[0-9]+", decimal_literal)]
+        #[regex("0[xX][0-9a-fA-F]+", hex_literal)]
+        #[regex(r"([0-9]+\.[0-9]*|\.[0-9]+)([eE][+-]?[0-9]+)?", float_literal)]
+        #[regex("[0-9]+[eE][+-]?[0-9]+", float_literal)]
+        Number(NumericLiteral),
+        #[regex(r#""(?:[^"\\\r\n]|\\(?:\r\n|.|\n))*""#, callback = string_literal)]
+        #[regex(r#"'(?:[^'\\\r\n]|\\(?:\r\n|.|\n))*'"#, callback = string_literal)]
         String(String),
 
         #[token("(")]
@@ -193,6 +565,22 @@ pub mod as3 {
         Colon,
         #[token(";")]
         Semicolon,
+        #[token(".")]
+        Dot,
+        #[token("..")]
+        DotDot,
+        #[token("...")]
+        Ellipsis,
+        #[token("::")]
+        ColonColon,
+        #[token("@")]
+        At,
+        #[token("[")]
+        LSquareBracket,
+        #[token("]")]
+        RSquareBracket,
+        #[token("?")]
+        QuestionMark,
 
         #[token("+")]
         OperatorAdd,
@@ -202,14 +590,481 @@ pub mod as3 {
         OperatorMul,
         #[token("/")]
         OperatorDiv,
+        #[token("%")]
+        OperatorMod,
+        #[token("!")]
+        OperatorNot,
+        #[token("~")]
+        OperatorBitNot,
+        #[token("<")]
+        OperatorLess,
+        #[token(">")]
+        OperatorGreater,
+        #[token("<=")]
+        OperatorLessEqual,
+        #[token(">=")]
+        OperatorGreaterEqual,
+        #[token("==")]
+        OperatorEqual,
+        #[token("!=")]
+        OperatorNotEqual,
+        #[token("===")]
+        OperatorStrictEqual,
+        #[token("!==")]
+        OperatorStrictNotEqual,
+        #[token("&&")]
+        OperatorAnd,
+        #[token("||")]
+        OperatorOr,
+        #[token("&")]
+        OperatorBitAnd,
+        #[token("|")]
+        OperatorBitOr,
+        #[token("^")]
+        OperatorBitXor,
+        #[token("<<")]
+        OperatorLeftShift,
+        #[token(">>")]
+        OperatorRightShift,
+        #[token(">>>")]
+        OperatorUnsignedRightShift,
+        #[token("++")]
+        OperatorIncrement,
+        #[token("--")]
+        OperatorDecrement,
+
+        #[token("+=")]
+        AssignAdd,
+        #[token("-=")]
+        AssignSub,
+        #[token("*=")]
+        AssignMul,
+        #[token("/=")]
+        AssignDiv,
+        #[token("%=")]
+        AssignMod,
+        #[token("<<=")]
+        AssignLeftShift,
+        #[token(">>=")]
+        AssignRightShift,
+        #[token(">>>=")]
+        AssignUnsignedRightShift,
+        #[token("&=")]
+        AssignBitAnd,
+        #[token("|=")]
+        AssignBitOr,
+        #[token("^=")]
+        AssignBitXor,
+        #[token("&&=")]
+        AssignAnd,
+        #[token("||=")]
+        AssignOr,
 
-        #[regex(r"\n", newline_callback)]
-        Newline,
+        /// Never produced by logos itself, a `/` or `/=` is relexed into this when no expression comes before it.
+        RegExp(RegExpLiteral),
+    }
+
+    impl Token {
+        /// Whether this token can be the last token of an expression.
+        ///
+        /// A `/` after such a token is a division, otherwise it starts a regular expression literal.
+        pub fn ends_expression(&self) -> bool {
+            matches!(
+                self,
+                Token::Identifier(_)
+                    | Token::Number(_)
+                    | Token::String(_)
+                    | Token::Bool(_)
+                    | Token::RegExp(_)
+                    // Contextual keywords can be used as names, e.g. `get / 2`.
+                    | Token::KeywordEach
+                    | Token::KeywordGet
+                    | Token::KeywordSet
+                    | Token::KeywordNamespace
+                    | Token::KeywordInclude
+                    | Token::KeywordDynamic
+                    | Token::KeywordFinal
+                    | Token::KeywordNative
+                    | Token::KeywordOverride
+                    | Token::KeywordStatic
+                    | Token::KeywordThis
+                    | Token::KeywordSuper
+                    | Token::KeywordNull
+                    | Token::RParen
+                    | Token::RSquareBracket
+                    | Token::OperatorIncrement
+                    | Token::OperatorDecrement
+            )
+        }
+    }
+
+    impl<'input> ContextualToken<'input> for Token {
+        fn relex(
+            &self,
+            previous: Option<&Self>,
+            source: &'input str,
+            start: usize,
+        ) -> Option<Result<(Self, usize), TokenError>> {
+            match self {
+                Token::OperatorDiv | Token::AssignDiv
+                    if !previous.is_some_and(Token::ends_expression) =>
+                {
+                    Some(
+                        regexp_literal(source, start)
+                            .map(|(literal, len)| (Token::RegExp(literal), len)),
+                    )
+                }
+                _ => None,
+            }
+        }
     }
 
     impl fmt::Display for Token {
         fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-            write!(f, "{:?}", self)
+            let text = match self {
+                Token::KeywordVar => "var",
+                Token::KeywordPackage => "package",
+                Token::KeywordClass => "class",
+                Token::KeywordFunction => "function",
+                Token::KeywordAs => "as",
+                Token::KeywordBreak => "break",
+                Token::KeywordCase => "case",
+                Token::KeywordCatch => "catch",
+                Token::KeywordConst => "const",
+                Token::KeywordContinue => "continue",
+                Token::KeywordDefault => "default",
+                Token::KeywordDelete => "delete",
+                Token::KeywordDo => "do",
+                Token::KeywordElse => "else",
+                Token::KeywordExtends => "extends",
+                Token::KeywordFinally => "finally",
+                Token::KeywordFor => "for",
+                Token::KeywordIf => "if",
+                Token::KeywordImplements => "implements",
+                Token::KeywordImport => "import",
+                Token::KeywordIn => "in",
+                Token::KeywordInstanceof => "instanceof",
+                Token::KeywordInterface => "interface",
+                Token::KeywordInternal => "internal",
+                Token::KeywordIs => "is",
+                Token::KeywordNew => "new",
+                Token::KeywordNull => "null",
+                Token::KeywordReturn => "return",
+                Token::KeywordSuper => "super",
+                Token::KeywordSwitch => "switch",
+                Token::KeywordThis => "this",
+                Token::KeywordThrow => "throw",
+                Token::KeywordTry => "try",
+                Token::KeywordTypeof => "typeof",
+                Token::KeywordUse => "use",
+                Token::KeywordVoid => "void",
+                Token::KeywordWhile => "while",
+                Token::KeywordWith => "with",
+                Token::KeywordEach => "each",
+                Token::KeywordGet => "get",
+                Token::KeywordSet => "set",
+                Token::KeywordNamespace => "namespace",
+                Token::KeywordInclude => "include",
+                Token::KeywordDynamic => "dynamic",
+                Token::KeywordFinal => "final",
+                Token::KeywordNative => "native",
+                Token::KeywordOverride => "override",
+                Token::KeywordStatic => "static",
+                Token::KeywordVisibility(visibility) => match visibility {
+                    Visibility::Public => "public",
+                    Visibility::Protected => "protected",
+                    Visibility::Private => "private",
+                },
+                Token::Bool(value) => return write!(f, "boolean `{}`", value),
+                Token::Identifier(name) => return write!(f, "identifier `{}`", name),
+                Token::Number(value) => return write!(f, "number `{}`", value),
+                Token::String(value) => return write!(f, "string {:?}", value),
+                Token::LParen => "(",
+                Token::RParen => ")",
+                Token::LCurlyBracket => "{",
+                Token::RCurlyBracket => "}",
+                Token::Assign => "=",
+                Token::Comma => ",",
+                Token::Colon => ":",
+                Token::Semicolon => ";",
+                Token::Dot => ".",
+                Token::DotDot => "..",
+                Token::Ellipsis => "...",
+                Token::ColonColon => "::",
+                Token::At => "@",
+                Token::LSquareBracket => "[",
+                Token::RSquareBracket => "]",
+                Token::QuestionMark => "?",
+                Token::OperatorAdd => "+",
+                Token::OperatorSub => "-",
+                Token::OperatorMul => "*",
+                Token::OperatorDiv => "/",
+                Token::OperatorMod => "%",
+                Token::OperatorNot => "!",
+                Token::OperatorBitNot => "~",
+                Token::OperatorLess => "<",
+                Token::OperatorGreater => ">",
+                Token::OperatorLessEqual => "<=",
+                Token::OperatorGreaterEqual => ">=",
+                Token::OperatorEqual => "==",
+                Token::OperatorNotEqual => "!=",
+                Token::OperatorStrictEqual => "===",
+                Token::OperatorStrictNotEqual => "!==",
+                Token::OperatorAnd => "&&",
+                Token::OperatorOr => "||",
+                Token::OperatorBitAnd => "&",
+                Token::OperatorBitOr => "|",
+                Token::OperatorBitXor => "^",
+                Token::OperatorLeftShift => "<<",
+                Token::OperatorRightShift => ">>",
+                Token::OperatorUnsignedRightShift => ">>>",
+                Token::OperatorIncrement => "++",
+                Token::OperatorDecrement => "--",
+                Token::AssignAdd => "+=",
+                Token::AssignSub => "-=",
+                Token::AssignMul => "*=",
+                Token::AssignDiv => "/=",
+                Token::AssignMod => "%=",
+                Token::AssignLeftShift => "<<=",
+                Token::AssignRightShift => ">>=",
+                Token::AssignUnsignedRightShift => ">>>=",
+                Token::AssignBitAnd => "&=",
+                Token::AssignBitOr => "|=",
+                Token::AssignBitXor => "^=",
+                Token::AssignAnd => "&&=",
+                Token::AssignOr => "||=",
+                Token::RegExp(literal) => {
+                    return write!(
+                        f,
+                        "regular expression `/{}/{}`",
+                        literal.pattern, literal.flags
+                    )
+                }
+            };
+
+            write!(f, "`{}`", text)
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn invalid_escape(start: usize, end: usize) -> TokenError {
+        TokenError::InvalidEscape { start, end }
+    }
+
+    #[test]
+    fn character_escapes() {
+        assert_eq!(
+            unescape(r"\n\r\t\b\f\v\0", 0).unwrap(),
+            "\n\r\t\u{8}\u{c}\u{b}\0"
+        );
+        // `\0` followed by a digit is not a null character.
+        assert_eq!(unescape(r"\01", 0).unwrap(), "01");
+        assert_eq!(unescape(r#"\\\'\"\q"#, 0).unwrap(), "\\'\"q");
+    }
+
+    #[test]
+    fn hex_and_unicode_escapes() {
+        assert_eq!(unescape(r"\x41\x7e", 0).unwrap(), "A~");
+        assert_eq!(unescape(r"\u0041\u00e9", 0).unwrap(), "Aé");
+        assert_eq!(unescape(r"\uD834\uDD1E", 0).unwrap(), "𝄞");
+        assert_eq!(unescape(r"\ud834\udd1e", 0).unwrap(), "𝄞");
+    }
+
+    #[test]
+    fn lone_surrogates() {
+        assert_eq!(unescape(r"\uD800", 0).unwrap(), "\u{FFFD}");
+        assert_eq!(unescape(r"\uDC00x", 0).unwrap(), "\u{FFFD}x");
+        // The escape after a high surrogate is kept if it is not a low surrogate.
+        assert_eq!(unescape(r"\uD800\u0041", 0).unwrap(), "\u{FFFD}A");
+        assert_eq!(unescape(r"\uD800\uD800", 0).unwrap(), "\u{FFFD}\u{FFFD}");
+        assert_eq!(unescape(r"\uD800\u12", 0), Err(invalid_escape(6, 10)));
+    }
+
+    #[test]
+    fn line_continuations() {
+        assert_eq!(unescape("a\\\nb", 0).unwrap(), "ab");
+        assert_eq!(unescape("a\\\r\nb", 0).unwrap(), "ab");
+        assert_eq!(unescape("a\\\rb", 0).unwrap(), "ab");
+        assert_eq!(unescape("a\\\u{2028}b", 0).unwrap(), "ab");
+    }
+
+    #[test]
+    fn invalid_escapes() {
+        // The span covers the escape up to the first character that does not belong to it.
+        assert_eq!(unescape(r"a\xZ1", 10), Err(invalid_escape(11, 13)));
+        assert_eq!(unescape(r"\x4", 0), Err(invalid_escape(0, 3)));
+        assert_eq!(unescape(r"\u12g4", 0), Err(invalid_escape(0, 4)));
+    }
+
+    #[test]
+    fn string_literals() {
+        let tokens = as3::Token::lexer(r#"'it\'s' "\x4" "#).collect::<Vec<_>>();
+
+        assert_eq!(tokens.len(), 2);
+        assert!(matches!(&tokens[0], Ok(as3::Token::String(s)) if s == "it's"));
+        // Errors are relative to the whole source, not the literal.
+        assert!(matches!(&tokens[1], Err(e) if *e == invalid_escape(9, 12)));
+    }
+
+    /// Lexes a single numeric literal.
+    fn number(source: &str) -> NumericLiteral {
+        match &as3::Token::lexer(source).collect::<Vec<_>>()[..] {
+            [Ok(as3::Token::Number(value))] => *value,
+            tokens => panic!(
+                "Expected a single number for {:?}, got {:?}",
+                source, tokens
+            ),
+        }
+    }
+
+    #[test]
+    fn integer_literals() {
+        assert_eq!(number("0"), NumericLiteral::Int(0));
+        assert_eq!(number("2147483647"), NumericLiteral::Int(i32::MAX));
+        // Integers that don't fit into an int become a uint, and a Number after that.
+        assert_eq!(number("2147483648"), NumericLiteral::Uint(2147483648));
+        assert_eq!(number("4294967295"), NumericLiteral::Uint(u32::MAX));
+        assert_eq!(number("4294967296"), NumericLiteral::Number(4294967296.0));
+        assert_eq!(
+            number("100000000000000000000"),
+            NumericLiteral::Number(1e20)
+        );
+    }
+
+    #[test]
+    fn hex_literals() {
+        assert_eq!(number("0x1f"), NumericLiteral::Int(31));
+        assert_eq!(number("0XfF"), NumericLiteral::Int(255));
+        assert_eq!(number("0x7fffffff"), NumericLiteral::Int(i32::MAX));
+        assert_eq!(number("0xffffffff"), NumericLiteral::Uint(u32::MAX));
+        assert_eq!(number("0x100000000"), NumericLiteral::Number(4294967296.0));
+        // Too big even for u64.
+        assert_eq!(
+            number("0x100000000000000000"),
+            NumericLiteral::Number(2f64.powi(68))
+        );
+    }
+
+    #[test]
+    fn float_literals() {
+        assert_eq!(number("1.5"), NumericLiteral::Number(1.5));
+        assert_eq!(number("1."), NumericLiteral::Number(1.0));
+        assert_eq!(number(".25"), NumericLiteral::Number(0.25));
+        // A float is always a Number, even if it has an integer value.
+        assert_eq!(number("3.0"), NumericLiteral::Number(3.0));
+    }
+
+    #[test]
+    fn exponents() {
+        assert_eq!(number("1e3"), NumericLiteral::Number(1000.0));
+        assert_eq!(number("2E+2"), NumericLiteral::Number(200.0));
+        assert_eq!(number("5e-1"), NumericLiteral::Number(0.5));
+        assert_eq!(number("1.5e2"), NumericLiteral::Number(150.0));
+        assert_eq!(number(".5E1"), NumericLiteral::Number(5.0));
+    }
+
+    fn regexp(pattern: &str, flags: &str) -> RegExpLiteral {
+        RegExpLiteral {
+            pattern: pattern.to_owned(),
+            flags: flags.to_owned(),
+        }
+    }
+
+    #[test]
+    fn regexp_literals() {
+        assert_eq!(regexp_literal("/ab+c/gi", 0), Ok((regexp("ab+c", "gi"), 8)));
+        assert_eq!(regexp_literal("x = /a/;", 4), Ok((regexp("a", ""), 3)));
+        // Flags end at the first character that can't be part of an identifier.
+        assert_eq!(regexp_literal("/a/g.test(s)", 0), Ok((regexp("a", "g"), 4)));
+    }
+
+    #[test]
+    fn regexp_slashes() {
+        assert_eq!(regexp_literal("/[/]x/", 0), Ok((regexp("[/]x", ""), 6)));
+        assert_eq!(regexp_literal(r"/a\/b/", 0), Ok((regexp(r"a\/b", ""), 6)));
+        assert_eq!(regexp_literal(r"/[\]/]/", 0), Ok((regexp(r"[\]/]", ""), 7)));
+    }
+
+    #[test]
+    fn unterminated_regexps() {
+        let unterminated = |start, end| Err(TokenError::UnterminatedRegExp { start, end });
+
+        // The error spans up to the end of the line.
+        assert_eq!(regexp_literal("x = /abc\ny/", 4), unterminated(4, 8));
+        assert_eq!(regexp_literal("/a\\\nb/", 0), unterminated(0, 3));
+        assert_eq!(regexp_literal("/[/\r\n", 0), unterminated(0, 3));
+        assert_eq!(regexp_literal("/abc", 0), unterminated(0, 4));
+    }
+
+    /// Lexes a source with the relexing lexer, giving the debug names of the tokens.
+    fn tokens(source: &str) -> Vec<String> {
+        crate::lexer::Lexer::<as3::Token>::new(source)
+            .map(|token| format!("{:?}", token.unwrap().1))
+            .collect()
+    }
+
+    #[test]
+    fn division_after_expression() {
+        assert_eq!(
+            tokens("a / b / c"),
+            [
+                "Identifier(\"a\")",
+                "OperatorDiv",
+                "Identifier(\"b\")",
+                "OperatorDiv",
+                "Identifier(\"c\")"
+            ]
+        );
+        assert_eq!(tokens("(a) / 2")[3], "OperatorDiv");
+        assert_eq!(tokens("a[0] /= 2")[4], "AssignDiv");
+        assert_eq!(tokens("this / 2")[1], "OperatorDiv");
+
+        for keyword in [
+            "each",
+            "get",
+            "set",
+            "namespace",
+            "include",
+            "dynamic",
+            "final",
+            "native",
+            "override",
+            "static",
+        ] {
+            let tokens = tokens(&format!("{} / x / 2", keyword));
+            assert_eq!(tokens[1], "OperatorDiv", "after `{}`", keyword);
+            assert_eq!(tokens[3], "OperatorDiv", "after `{}`", keyword);
+        }
+    }
+
+    #[test]
+    fn regexp_after_operator() {
+        assert_eq!(
+            tokens("( /re/g )"),
+            [
+                "LParen",
+                "RegExp(RegExpLiteral { pattern: \"re\", flags: \"g\" })",
+                "RParen"
+            ]
+        );
+        assert_eq!(
+            tokens("x = /=a/"),
+            [
+                "Identifier(\"x\")",
+                "Assign",
+                "RegExp(RegExpLiteral { pattern: \"=a\", flags: \"\" })"
+            ]
+        );
+        assert_eq!(
+            tokens("return /a/"),
+            [
+                "KeywordReturn",
+                "RegExp(RegExpLiteral { pattern: \"a\", flags: \"\" })"
+            ]
+        );
+    }
+}
@@ -1,16 +1,31 @@
-use crate::ast::Visitor;
+use crate::ast::Folder;
+use crate::diagnostic::{Diagnostic, Severity};
+use crate::source_map::SourceMap;
 use clap::Parser;
+use std::{ffi::OsString, fmt, path::PathBuf};
 use tracing_subscriber::filter::{EnvFilter, LevelFilter};
 
 mod ast;
 mod codegen;
+mod config;
+mod diagnostic;
 mod lexer;
 mod optimizer;
 mod parser;
+mod source_map;
 
 #[derive(Debug, Parser)]
 #[command(version, about, long_about = None)]
 struct Opt {
+    /// File to compile, files ending with `.abs` are treated as ActionScript assembly.
+    file: PathBuf,
+
+    /// Defines a configuration constant for conditional compilation, e.g. `--define CONFIG::debug,true`.
+    ///
+    /// The mxmlc forms `-define=...` and `-define+=...` are accepted too.
+    #[arg(long = "define", value_name = "NAMESPACE::NAME,VALUE")]
+    defines: Vec<config::Define>,
+
     #[clap(flatten, next_help_heading = "Debug Options")]
     debug: DebugFlags,
 }
@@ -31,7 +46,7 @@ impl DebugFlags {
 }
 
 fn main() {
-    let args = Opt::parse();
+    let args = Opt::parse_from(std::env::args_os().map(mxmlc_argument));
 
     let subscriber = tracing_subscriber::fmt::Subscriber::builder()
         .with_env_filter(
@@ -43,21 +58,110 @@ fn main() {
     // Ignore error if it's already been set
     let _ = tracing::subscriber::set_global_default(subscriber);
 
-    let file = std::fs::read_to_string("bug_612641.abs").unwrap();
-    let lexer = lexer::Lexer::<crate::lexer::asm::Token>::new(&file);
+    let file = match std::fs::read_to_string(&args.file) {
+        Ok(file) => file,
+        Err(e) => {
+            eprintln!("error: could not read {}: {}", args.file.display(), e);
+            std::process::exit(1);
+        }
+    };
+
+    let is_assembly = args.file.extension().is_some_and(|ext| ext == "abs");
+
+    // Assembly has no include directive, AS3 files are combined with the files they include.
+    let (source_map, include_errors) = if is_assembly {
+        let source_map = SourceMap::new(args.file.display().to_string(), file);
+        (source_map, Vec::new())
+    } else {
+        parser::as3::include::load(&args.file, file)
+    };
 
     if args.debug.any_set() {
         if args.debug.print_tokens {
-            for token in lexer {
-                tracing::info!("{:?}", token);
+            if is_assembly {
+                print_tokens::<lexer::asm::Token>(&source_map);
+            } else {
+                print_tokens::<lexer::as3::Token>(&source_map);
             }
         }
 
         return;
     }
 
-    let parser = parser::asm::grammar::FunctionsParser::new();
-    let out = parser.parse(lexer).unwrap();
+    let mut config = config::ConfigConstants::default();
+    let mut define_errors = false;
+    for define in &args.defines {
+        let source_map = SourceMap::new(format!("<define {}>", define.name), define.value.clone());
+
+        for diagnostic in config.define(define) {
+            eprint!("{}", diagnostic.render(&source_map));
+            define_errors |= diagnostic.severity == Severity::Error;
+        }
+    }
+
+    if define_errors {
+        std::process::exit(1);
+    }
+
+    let diagnostics = if is_assembly {
+        let lexer = lexer::Lexer::<lexer::asm::Token>::new(source_map.source());
+        let parser = parser::asm::grammar::FunctionsParser::new();
+        match parser.parse(lexer) {
+            Ok(out) => {
+                println!("{:#?}", out);
+                Vec::new()
+            }
+            Err(e) => vec![Diagnostic::from(e)],
+        }
+    } else {
+        let (package, parse_errors) = parser::as3::parse_package(source_map.source());
+        let mut diagnostics = include_errors;
+        diagnostics.extend(parse_errors);
+        if let Some(package) = package {
+            // Disabled code is removed before anything else looks at the package.
+            let package = config.fold_package(package);
+            diagnostics.append(&mut config.take_errors());
+            // Substituted constants are evaluated along with the rest of the constant expressions.
+            let package = optimizer::AstOptimizer::default().fold_package(package);
+
+            println!("{:#?}", package);
+        }
+        diagnostics
+    };
+
+    for diagnostic in &diagnostics {
+        eprint!("{}", diagnostic.render(&source_map));
+    }
+
+    // Warnings alone don't fail the build.
+    if diagnostics
+        .iter()
+        .any(|diagnostic| diagnostic.severity == Severity::Error)
+    {
+        std::process::exit(1);
+    }
+}
 
-    println!("{:#?}", out);
+/// Rewrites the mxmlc style `-define=...` and `-define+=...` arguments into `--define=...`.
+fn mxmlc_argument(arg: OsString) -> OsString {
+    match arg.to_str() {
+        Some(value) if value.starts_with("-define=") || value.starts_with("-define+=") => {
+            let (_, value) = value.split_once('=').unwrap();
+            format!("--define={}", value).into()
+        }
+        _ => arg,
+    }
+}
+
+fn print_tokens<'a, T>(source_map: &'a SourceMap)
+where
+    T: lexer::ContextualToken<'a> + fmt::Debug,
+    T::Extras: Default,
+{
+    for token in lexer::Lexer::<T>::new(source_map.source()) {
+        match token {
+            Ok((start, token, _)) => tracing::info!("{}: {:?}", source_map.location(start), token),
+            Err(e) => eprint!("{}", Diagnostic::from(e).render(source_map)),
+        }
+    }
 }
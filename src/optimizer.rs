@@ -1,7 +1,7 @@
 pub use ast::Optimizer as AstOptimizer;
 
 mod ast {
-    use crate::ast::{Expression, Folder, Operator};
+    use crate::ast::{fold::walk_expression, Expression, Folder, Operator, UnaryOperator};
     use std::borrow::Cow;
 
     /// Experimental Abstract Syntax Tree optimizer.
@@ -9,60 +9,502 @@ mod ast {
     /// # Goal
     ///
     /// Goal of the optimizer is to simplify expressions that can be evaluted at compile-time to reduce runtime evaluation costs.
+    #[derive(Default)]
     pub struct Optimizer {}
 
     impl Folder for Optimizer {
-        fn fold_expression(&mut self, v: Box<Expression>) -> Box<Expression> {
-            match *v {
+        fn fold_expression(&mut self, v: Expression) -> Expression {
+            match v {
+                Expression::BinaryOperation {
+                    lhs,
+                    operator: operator @ (Operator::And | Operator::Or),
+                    rhs,
+                } => {
+                    let lhs = self.fold_expression(*lhs);
+                    let rhs = self.fold_expression(*rhs);
+
+                    // The result is the left hand side if it decides the result, otherwise the right hand side.
+                    match (as_bool(&lhs), &operator) {
+                        (Some(false), Operator::And) | (Some(true), Operator::Or) => lhs,
+                        (Some(_), _) => rhs,
+                        (None, _) => Expression::BinaryOperation {
+                            lhs: Box::new(lhs),
+                            operator,
+                            rhs: Box::new(rhs),
+                        },
+                    }
+                }
+                Expression::BinaryOperation { lhs, operator, rhs } if operator.is_comparison() => {
+                    let lhs = self.fold_expression(*lhs);
+                    let rhs = self.fold_expression(*rhs);
+
+                    match evaluate_comparison(&lhs, &operator, &rhs) {
+                        Some(value) => Expression::Bool(value),
+                        None => Expression::BinaryOperation {
+                            lhs: Box::new(lhs),
+                            operator,
+                            rhs: Box::new(rhs),
+                        },
+                    }
+                }
+                Expression::BinaryOperation { lhs, operator, rhs } if operator.is_bitwise() => {
+                    let lhs = self.fold_expression(*lhs);
+                    let rhs = self.fold_expression(*rhs);
+
+                    match (as_number(&lhs), as_number(&rhs)) {
+                        (Some(lhs), Some(rhs)) => evaluate_bitwise(lhs, &operator, rhs),
+                        _ => Expression::BinaryOperation {
+                            lhs: Box::new(lhs),
+                            operator,
+                            rhs: Box::new(rhs),
+                        },
+                    }
+                }
                 Expression::BinaryOperation { lhs, operator, rhs } => {
-                    let lhs = self.fold_expression(lhs);
-                    let rhs = self.fold_expression(rhs);
+                    let lhs = self.fold_expression(*lhs);
+                    let rhs = self.fold_expression(*rhs);
 
-                    match (*lhs, *rhs) {
+                    match (lhs, rhs) {
                         // Pre-evaluate integer operations.
                         (Expression::Integer(value1), Expression::Integer(value2)) => {
-                            Box::new(Expression::Integer(match operator {
-                                Operator::Add => value1 + value2,
-                                // FIXME: Probably throw error here when second val is 0.
-                                Operator::Div => value1 / value2,
-                                Operator::Mul => value1 * value2,
-                                Operator::Sub => value1 - value2,
-                            }))
+                            let result = match operator {
+                                Operator::Add => value1.checked_add(value2),
+                                Operator::Sub => value1.checked_sub(value2),
+                                // A zero result is -0 if the signs differ, which an int can't hold.
+                                Operator::Mul => value1
+                                    .checked_mul(value2)
+                                    .filter(|result| *result != 0 || (value1 >= 0 && value2 >= 0)),
+                                // Division only stays an integer if there is no remainder.
+                                Operator::Div => value1
+                                    .checked_rem(value2)
+                                    .filter(|rem| *rem == 0)
+                                    .and_then(|_| value1.checked_div(value2))
+                                    .filter(|result| *result != 0 || value2 > 0),
+                                // The result has the sign of the dividend, which an int can't do for -0.
+                                Operator::Mod => value1
+                                    .checked_rem(value2)
+                                    .filter(|rem| *rem != 0 || value1 >= 0),
+                                _ => unreachable!("{:?} is not an arithmetic operator", operator),
+                            };
+
+                            match result {
+                                Some(value) => Expression::Integer(value),
+                                // Overflowing the int range (or a fractional result) yields a Number, like in the AVM2.
+                                None => Expression::Number(evaluate_number(
+                                    value1 as f64,
+                                    operator,
+                                    value2 as f64,
+                                )),
+                            }
+                        }
+                        // Pre-evaluate operations involving any other kind of number.
+                        (lhs, rhs) if as_number(&lhs).is_some() && as_number(&rhs).is_some() => {
+                            Expression::Number(evaluate_number(
+                                as_number(&lhs).unwrap(),
+                                operator,
+                                as_number(&rhs).unwrap(),
+                            ))
                         }
-                        // Optimize string concat operations.
-                        (Expression::String(lhs), rhs) => {
+                        // Optimize string concat operations, other operators convert the string into a number.
+                        (Expression::String(lhs), rhs) if operator == Operator::Add => {
                             if let Ok(rhs) = TryInto::<Cow<'_, String>>::try_into(&rhs) {
                                 let mut buf = String::new();
 
                                 buf.push_str(&lhs);
                                 buf.push_str(&rhs);
 
-                                Box::new(Expression::String(buf))
+                                Expression::String(buf)
                             } else {
-                                Box::new(Expression::BinaryOperation {
+                                Expression::BinaryOperation {
                                     lhs: Box::new(Expression::String(lhs)),
                                     operator,
                                     rhs: Box::new(rhs),
-                                })
+                                }
                             }
                         }
                         // Needs to be evaluated at runtime.
-                        (lhs, rhs) => Box::new(Expression::BinaryOperation {
+                        (lhs, rhs) => Expression::BinaryOperation {
                             lhs: Box::new(lhs),
                             operator,
                             rhs: Box::new(rhs),
-                        }),
+                        },
+                    }
+                }
+                Expression::UnaryOperation {
+                    operator: UnaryOperator::BitNot,
+                    value,
+                } => {
+                    let value = self.fold_expression(*value);
+
+                    match as_number(&value) {
+                        Some(value) => Expression::Integer(!to_int32(value)),
+                        None => Expression::UnaryOperation {
+                            operator: UnaryOperator::BitNot,
+                            value: Box::new(value),
+                        },
                     }
                 }
-                // Cannot optimize any further.
-                x => Box::new(x),
+                Expression::UnaryOperation {
+                    operator: UnaryOperator::Not,
+                    value,
+                } => {
+                    let value = self.fold_expression(*value);
+
+                    match as_bool(&value) {
+                        Some(value) => Expression::Bool(!value),
+                        None => Expression::UnaryOperation {
+                            operator: UnaryOperator::Not,
+                            value: Box::new(value),
+                        },
+                    }
+                }
+                Expression::UnaryOperation {
+                    operator: UnaryOperator::Negate,
+                    value,
+                } => {
+                    let value = self.fold_expression(*value);
+
+                    match value {
+                        // Zero is negated into -0, which is not an int.
+                        Expression::Integer(value) if value != 0 => match value.checked_neg() {
+                            Some(value) => Expression::Integer(value),
+                            None => Expression::Number(-(value as f64)),
+                        },
+                        // -2147483648 is an int again.
+                        Expression::Uint(value) if value == 1 << 31 => {
+                            Expression::Integer(i32::MIN)
+                        }
+                        ref value if as_number(value).is_some() => {
+                            Expression::Number(-as_number(value).unwrap())
+                        }
+                        value => Expression::UnaryOperation {
+                            operator: UnaryOperator::Negate,
+                            value: Box::new(value),
+                        },
+                    }
+                }
+                Expression::UnaryOperation {
+                    operator: UnaryOperator::Plus,
+                    value,
+                } => {
+                    let value = self.fold_expression(*value);
+
+                    // Numbers are already converted.
+                    if as_number(&value).is_some() {
+                        value
+                    } else {
+                        Expression::UnaryOperation {
+                            operator: UnaryOperator::Plus,
+                            value: Box::new(value),
+                        }
+                    }
+                }
+                Expression::Ternary {
+                    condition,
+                    if_true,
+                    if_false,
+                } => {
+                    let condition = self.fold_expression(*condition);
+
+                    // Only the chosen value has to be kept if the condition is constant.
+                    match as_bool(&condition) {
+                        Some(true) => self.fold_expression(*if_true),
+                        Some(false) => self.fold_expression(*if_false),
+                        None => Expression::Ternary {
+                            condition: Box::new(condition),
+                            if_true: Box::new(self.fold_expression(*if_true)),
+                            if_false: Box::new(self.fold_expression(*if_false)),
+                        },
+                    }
+                }
+                // Cannot optimize this expression itself, but maybe its sub-expressions.
+                x => walk_expression(self, x),
             }
         }
     }
 
-    impl Default for Optimizer {
-        fn default() -> Self {
-            Self {}
+    /// Returns the value of a numeric literal.
+    fn as_number(v: &Expression) -> Option<f64> {
+        match v {
+            Expression::Integer(v) => Some(*v as f64),
+            Expression::Uint(v) => Some(*v as f64),
+            Expression::Number(v) => Some(*v),
+            _ => None,
+        }
+    }
+
+    /// Returns the truthiness of a literal, the same way the AVM2 converts it into a Boolean.
+    fn as_bool(v: &Expression) -> Option<bool> {
+        match v {
+            Expression::Bool(v) => Some(*v),
+            Expression::Integer(v) => Some(*v != 0),
+            Expression::Uint(v) => Some(*v != 0),
+            Expression::Number(v) => Some(*v != 0.0 && !v.is_nan()),
+            Expression::String(v) => Some(!v.is_empty()),
+            Expression::RegExp { .. } => Some(true),
+            Expression::Null => Some(false),
+            _ => None,
+        }
+    }
+
+    /// Compares two literals, if the result does not depend on conversions done at runtime.
+    fn evaluate_comparison(
+        lhs: &Expression,
+        operator: &Operator,
+        rhs: &Expression,
+    ) -> Option<bool> {
+        if let (Some(lhs), Some(rhs)) = (as_number(lhs), as_number(rhs)) {
+            // Comparisons with NaN are false, except for inequality. Rust follows the same rules.
+            return Some(match operator {
+                Operator::Less => lhs < rhs,
+                Operator::LessEqual => lhs <= rhs,
+                Operator::Greater => lhs > rhs,
+                Operator::GreaterEqual => lhs >= rhs,
+                Operator::Equal | Operator::StrictEqual => lhs == rhs,
+                Operator::NotEqual | Operator::StrictNotEqual => lhs != rhs,
+                _ => unreachable!("{:?} is not a comparison", operator),
+            });
+        }
+
+        let equal = match (lhs, rhs) {
+            (Expression::String(lhs), Expression::String(rhs)) => lhs == rhs,
+            (Expression::Bool(lhs), Expression::Bool(rhs)) => lhs == rhs,
+            (Expression::Null, Expression::Null) => true,
+            _ => return None,
+        };
+
+        match operator {
+            Operator::Equal | Operator::StrictEqual => Some(equal),
+            Operator::NotEqual | Operator::StrictNotEqual => Some(!equal),
+            // FIXME: Strings are ordered by UTF-16 code units.
+            _ => None,
         }
     }
+
+    fn evaluate_number(lhs: f64, operator: Operator, rhs: f64) -> f64 {
+        match operator {
+            Operator::Add => lhs + rhs,
+            Operator::Sub => lhs - rhs,
+            Operator::Mul => lhs * rhs,
+            // Division by zero results in Infinity or NaN, same as at runtime.
+            Operator::Div => lhs / rhs,
+            // Rust's remainder has the sign of the dividend, like ECMAScript's.
+            Operator::Mod => lhs % rhs,
+            _ => unreachable!("{:?} is not an arithmetic operator", operator),
+        }
+    }
+
+    fn evaluate_bitwise(lhs: f64, operator: &Operator, rhs: f64) -> Expression {
+        // Only the lowest five bits of the shift count are used.
+        let shift = to_uint32(rhs) & 0x1f;
+
+        let result = match operator {
+            Operator::BitAnd => to_int32(lhs) & to_int32(rhs),
+            Operator::BitOr => to_int32(lhs) | to_int32(rhs),
+            Operator::BitXor => to_int32(lhs) ^ to_int32(rhs),
+            Operator::LeftShift => to_int32(lhs) << shift,
+            Operator::RightShift => to_int32(lhs) >> shift,
+            // This is the only bitwise operator resulting in a uint.
+            Operator::UnsignedRightShift => {
+                let result = to_uint32(lhs) >> shift;
+                return match i32::try_from(result) {
+                    Ok(result) => Expression::Integer(result),
+                    Err(_) => Expression::Uint(result),
+                };
+            }
+            _ => unreachable!("{:?} is not a bitwise operator", operator),
+        };
+
+        Expression::Integer(result)
+    }
+
+    /// Converts a Number into a int, as described in section 9.5 of ECMA-262.
+    fn to_int32(value: f64) -> i32 {
+        to_uint32(value) as i32
+    }
+
+    /// Converts a Number into a uint, as described in section 9.6 of ECMA-262.
+    fn to_uint32(value: f64) -> u32 {
+        if !value.is_finite() {
+            return 0;
+        }
+
+        // The value is wrapped into the range of a uint, which is exact since it is truncated first.
+        value.trunc().rem_euclid(4294967296.0) as u32
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::AstOptimizer;
+    use crate::ast::{Expression, Folder};
+    use crate::parser::as3::parse_expression;
+
+    fn optimize(source: &str) -> Expression {
+        let (expression, errors) = parse_expression(source);
+        assert!(errors.is_empty(), "{}: {:?}", source, errors);
+        AstOptimizer::default().fold_expression(*expression.unwrap())
+    }
+
+    fn number(value: f64) -> Expression {
+        Expression::Number(value)
+    }
+
+    fn variable(name: &str) -> Expression {
+        Expression::Variable(name.to_owned())
+    }
+
+    fn assert_folds(cases: &[(&str, Expression)]) {
+        for (source, expected) in cases {
+            assert_eq!(&optimize(source), expected, "{}", source);
+        }
+    }
+
+    #[test]
+    fn arithmetic() {
+        assert_folds(&[
+            ("1 + 2", Expression::Integer(3)),
+            ("2 * 3 - 10", Expression::Integer(-4)),
+            ("6 / 3", Expression::Integer(2)),
+            ("7 / 2", number(3.5)),
+            ("1 / 0", number(f64::INFINITY)),
+            ("-7 % 2", Expression::Integer(-1)),
+            ("0 * 5", Expression::Integer(0)),
+            ("0 / 5", Expression::Integer(0)),
+            ("1.5 * 2", number(3.0)),
+            ("0.5 + 1", number(1.5)),
+            ("'a' + 'b'", Expression::String("ab".to_owned())),
+            ("'a' + 1", Expression::String("a1".to_owned())),
+            ("+2", Expression::Integer(2)),
+            ("-(1 + 2)", Expression::Integer(-3)),
+        ]);
+    }
+
+    #[test]
+    fn int_overflow_becomes_a_number() {
+        assert_folds(&[
+            ("2147483647 + 1", number(2147483648.0)),
+            ("-2147483648 - 1", number(-2147483649.0)),
+            ("65536 * 65536", number(4294967296.0)),
+            ("-2147483648", Expression::Integer(i32::MIN)),
+            ("-(-2147483648)", number(2147483648.0)),
+            ("-2147483648 / -1", number(2147483648.0)),
+        ]);
+    }
+
+    #[test]
+    fn negative_zero() {
+        for source in [
+            "-0", "-(0)", "-4 % 2", "0 * -1", "-1 * 0", "0 / -3", "0 * -1.5",
+        ] {
+            match optimize(source) {
+                Expression::Number(value) => {
+                    assert!(value == 0.0 && value.is_sign_negative(), "{}", source)
+                }
+                other => panic!("{} folded into {:?}", source, other),
+            }
+        }
+    }
+
+    #[test]
+    fn comparisons() {
+        assert_folds(&[
+            ("1 < 2", Expression::Bool(true)),
+            ("2 <= 1", Expression::Bool(false)),
+            ("3 >= 3", Expression::Bool(true)),
+            ("1 == 1.0", Expression::Bool(true)),
+            ("0xFFFFFFFF > 0", Expression::Bool(true)),
+            ("'a' == 'a'", Expression::Bool(true)),
+            ("'a' !== 'b'", Expression::Bool(true)),
+            ("true != false", Expression::Bool(true)),
+            ("null == null", Expression::Bool(true)),
+            ("0 / 0 == 0 / 0", Expression::Bool(false)),
+            ("0 / 0 != 0 / 0", Expression::Bool(true)),
+        ]);
+
+        // Strings are not ordered yet, and mixed types are converted at runtime.
+        for source in ["'a' < 'b'", "'1' == 1", "x == 1"] {
+            assert!(
+                matches!(optimize(source), Expression::BinaryOperation { .. }),
+                "{}",
+                source
+            );
+        }
+    }
+
+    #[test]
+    fn bitwise() {
+        assert_folds(&[
+            ("0xFFFFFFFF | 0", Expression::Integer(-1)),
+            ("4294967296 | 0", Expression::Integer(0)),
+            ("-1.5 | 0", Expression::Integer(-1)),
+            ("6 & 3", Expression::Integer(2)),
+            ("6 ^ 3", Expression::Integer(5)),
+            ("~0", Expression::Integer(-1)),
+            ("~0xFFFFFFFF", Expression::Integer(0)),
+            ("~1.5", Expression::Integer(-2)),
+        ]);
+    }
+
+    #[test]
+    fn shifts() {
+        assert_folds(&[
+            ("1 << 32", Expression::Integer(1)),
+            ("1 << 31", Expression::Integer(i32::MIN)),
+            ("1 << -1", Expression::Integer(i32::MIN)),
+            ("-8 >> 1", Expression::Integer(-4)),
+            ("-1 >>> 0", Expression::Uint(u32::MAX)),
+            ("-1 >>> 1", Expression::Integer(i32::MAX)),
+            ("0xFFFFFFFF >>> 0", Expression::Uint(u32::MAX)),
+            ("1 / 0 | 0", Expression::Integer(0)),
+        ]);
+    }
+
+    #[test]
+    fn strings_only_fold_concatenation() {
+        for source in ["'a' % 1", "'a' - 1", "'6' * 2", "'a' / 'b'"] {
+            assert!(
+                matches!(optimize(source), Expression::BinaryOperation { .. }),
+                "{}",
+                source
+            );
+        }
+    }
+
+    #[test]
+    fn logical() {
+        assert_folds(&[
+            ("!0", Expression::Bool(true)),
+            ("!'a'", Expression::Bool(false)),
+            ("!null", Expression::Bool(true)),
+            ("true && x", variable("x")),
+            ("0 && x", Expression::Integer(0)),
+            ("0 || 'a'", Expression::String("a".to_owned())),
+            ("1 < 2 ? x : y", variable("x")),
+            ("'' ? x : y", variable("y")),
+        ]);
+    }
+
+    #[test]
+    fn literal_elements() {
+        let integer = |value| Some(Box::new(Expression::Integer(value)));
+
+        // Elements and property values are folded, holes are kept.
+        assert_folds(&[
+            (
+                "[1 + 2, , x]",
+                Expression::Array(vec![integer(3), None, Some(Box::new(variable("x")))]),
+            ),
+            (
+                "{a: 2 * 3, 'b': [4 - 1]}",
+                Expression::Object(vec![
+                    ("a".to_owned(), Box::new(Expression::Integer(6))),
+                    (
+                        "b".to_owned(),
+                        Box::new(Expression::Array(vec![integer(3)])),
+                    ),
+                ]),
+            ),
+        ]);
+    }
 }
@@ -1,10 +1,283 @@
 //! ActionScript 3 grammar parser.
 
 use crate::ast;
+use crate::diagnostic::Diagnostic;
+use crate::lexer::{as3::Token, Lexer, LexicalError, Spanned};
+use lalrpop_util::{ErrorRecovery, ParseError};
+use std::ops::Range;
 
+mod asi;
+// Generated by lalrpop, which exports every parser and does not produce clippy clean code.
+#[allow(clippy::all, unused_imports)]
 pub mod grammar;
+pub mod include;
 
 pub enum ClassPart {
     Member(ast::ClassMember),
     Function(ast::Function),
 }
+
+/// Tokens as they are fed into the grammar, after invalid tokens have been removed and semicolons inserted.
+type TokenStream<'input> = dyn Iterator<Item = Spanned<Token, usize, LexicalError>> + 'input;
+
+/// Parses a source file containing a package.
+///
+/// Parsing continues after errors where possible, so a partial package can be returned alongside every error
+/// found in the file.
+pub fn parse_package(source: &str) -> (Option<ast::Package>, Vec<Diagnostic>) {
+    parse(source, |errors, tokens| {
+        grammar::PackageParser::new().parse(source, errors, tokens)
+    })
+}
+
+/// Parses a standalone expression, such as the value of a configuration constant given on the command line.
+pub fn parse_expression(source: &str) -> (Option<Box<ast::Expression>>, Vec<Diagnostic>) {
+    parse(source, |errors, tokens| {
+        grammar::ExpressionParser::new().parse(source, errors, tokens)
+    })
+}
+
+fn parse<T>(
+    source: &str,
+    parse: impl FnOnce(
+        &mut Vec<ErrorRecovery<usize, Token, LexicalError>>,
+        &mut TokenStream,
+    ) -> Result<T, ParseError<usize, Token, LexicalError>>,
+) -> (Option<T>, Vec<Diagnostic>) {
+    let mut lexical_errors = Vec::new();
+    let mut errors = Vec::new();
+
+    // Invalid tokens are reported, but left out so that the parser can continue.
+    let tokens = Lexer::<Token>::new(source).filter_map(|token| match token {
+        Ok(token) => Some(Ok(token)),
+        Err(e) => {
+            lexical_errors.push(e);
+            None
+        }
+    });
+    let mut tokens = asi::AutomaticSemicolons::new(source, tokens);
+
+    let result = parse(&mut errors, &mut tokens);
+
+    let mut diagnostics = lexical_errors
+        .into_iter()
+        .map(Diagnostic::from)
+        .chain(errors.into_iter().map(|e| parse_error(source, e.error)))
+        .collect::<Vec<_>>();
+
+    let output = match result {
+        Ok(output) => Some(output),
+        Err(e) => {
+            diagnostics.push(parse_error(source, e));
+            None
+        }
+    };
+
+    diagnostics.sort_by_key(|diagnostic| diagnostic.primary.as_ref().map(|label| label.span.start));
+
+    (output, diagnostics)
+}
+
+/// Converts a parse error into a diagnostic, an unexpected end of file also points at the delimiter left open.
+fn parse_error(source: &str, error: ParseError<usize, Token, LexicalError>) -> Diagnostic {
+    let is_eof = matches!(error, ParseError::UnrecognizedEof { .. });
+    let diagnostic = Diagnostic::from(error);
+
+    match unclosed_delimiter(source) {
+        Some(span) if is_eof => diagnostic.with_secondary(span, "unclosed delimiter"),
+        _ => diagnostic,
+    }
+}
+
+/// Finds the innermost bracket that is never closed.
+fn unclosed_delimiter(source: &str) -> Option<Range<usize>> {
+    let mut open = Vec::new();
+    for (start, token, end) in Lexer::<Token>::new(source).filter_map(Result::ok) {
+        match token {
+            Token::LParen | Token::LCurlyBracket | Token::LSquareBracket => open.push(start..end),
+            Token::RParen | Token::RCurlyBracket | Token::RSquareBracket => {
+                open.pop();
+            }
+            _ => {}
+        }
+    }
+
+    open.pop()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn numeric_property_names() {
+        // Property names are the string a number converts into at runtime.
+        for (key, name) in [
+            ("0x10", "16"),
+            ("2.0", "2"),
+            ("1.5", "1.5"),
+            ("1e21", "1e+21"),
+            ("1e-7", "1e-7"),
+        ] {
+            let (expression, errors) = parse_expression(&format!("{{ {key}: 1 }}"));
+            assert!(errors.is_empty(), "{:?}", errors);
+
+            match *expression.unwrap() {
+                ast::Expression::Object(properties) => assert_eq!(properties[0].0, name),
+                other => panic!("Expected an object, got {:?}", other),
+            }
+        }
+    }
+
+    #[test]
+    fn unclosed_delimiter_at_end_of_file() {
+        let source = "package { public class A { public function f(): void { var x = (1); }\n";
+        let (_, errors) = parse_package(source);
+
+        let [error] = &errors[..] else {
+            panic!("Expected a single error, got {:?}", errors);
+        };
+        assert_eq!(error.message, "unexpected end of file");
+        let [label] = &error.secondary[..] else {
+            panic!(
+                "Expected a single secondary label, got {:?}",
+                error.secondary
+            );
+        };
+        // The class is the innermost bracket left open.
+        assert_eq!(label.span, 25..26);
+        assert_eq!(label.message, "unclosed delimiter");
+
+        // Other errors don't point at brackets.
+        let (_, errors) = parse_package("package { public class A { public var 5; } }");
+        assert!(errors[0].secondary.is_empty());
+    }
+
+    #[test]
+    fn independent_errors() {
+        let source = "package { public class A {
+            public function f(): void { var a = 1; var = 2; var c = 3; }
+            public var 5;
+            public function g(): void { var b = @; var d = 4; }
+            public function h(): void { }
+        } }";
+        let (package, errors) = parse_package(source);
+
+        // Every error is reported, in the order they appear in the source.
+        let labels = errors
+            .iter()
+            .map(|error| &source[error.primary.as_ref().unwrap().span.clone()])
+            .collect::<Vec<_>>();
+        assert_eq!(labels, ["=", "5", "@"]);
+
+        // The broken member is left out, the functions around the errors are kept.
+        let class = &package.expect("Expected a partial package").classes[0];
+        assert!(class.members.is_empty());
+        let functions = class.functions.iter().map(|f| &f.name).collect::<Vec<_>>();
+        assert_eq!(functions, ["f", "g", "h"]);
+        assert!(class.functions[2].block.is_empty());
+
+        // Parsing continues after a broken statement.
+        for (function, name, value) in
+            [(&class.functions[0], "c", 3), (&class.functions[1], "d", 4)]
+        {
+            let Some(ast::Statement::Variable {
+                name: variable,
+                value: assigned,
+                ..
+            }) = function.block.last()
+            else {
+                panic!("Expected a variable declaration, got {:?}", function.block);
+            };
+            assert_eq!(variable, name);
+            assert_eq!(**assigned, ast::Expression::Integer(value));
+        }
+    }
+
+    #[test]
+    fn prefix_operator_starts_statement_body() {
+        for body in [
+            "if (x)\n++i;",
+            "if (x) i = 0; else\n--i;",
+            "while (c)\n++i;",
+            "for (var i: int = 0; i < n; i++)\n--n;",
+            "for (var k: String in o)\n++i;",
+            "for each (var v: int in o)\n++i;",
+        ] {
+            let source = format!(
+                "package {{ public class Test {{ public function test(): void {{ {body} }} }} }}"
+            );
+            let (package, errors) = parse_package(&source);
+            assert!(errors.is_empty(), "{}: {:?}", body, errors);
+            assert!(package.is_some());
+        }
+    }
+
+    #[test]
+    fn ternary_precedence() {
+        let parse = |source: &str| {
+            let (expression, errors) = parse_expression(source);
+            assert!(errors.is_empty(), "{}: {:?}", source, errors);
+            *expression.unwrap()
+        };
+        let variable = |name: &str| Box::new(ast::Expression::Variable(name.to_owned()));
+        let ternary = |condition, if_true, if_false| ast::Expression::Ternary {
+            condition,
+            if_true,
+            if_false,
+        };
+
+        // The else arm extends as far as possible, so ternaries nest to the right.
+        assert_eq!(
+            parse("a ? b : c ? d : e"),
+            ternary(
+                variable("a"),
+                variable("b"),
+                Box::new(ternary(variable("c"), variable("d"), variable("e")))
+            )
+        );
+        // Both arms can be assignments.
+        assert_eq!(
+            parse("a ? b = c : d = e"),
+            ternary(
+                variable("a"),
+                Box::new(parse("b = c")),
+                Box::new(parse("d = e"))
+            )
+        );
+        // The condition binds looser than `||`, and tighter than an assignment.
+        assert_eq!(
+            parse("a || b ? c : d"),
+            ternary(Box::new(parse("a || b")), variable("c"), variable("d"))
+        );
+        assert_eq!(
+            parse("x = a ? b : c"),
+            ast::Expression::Assignment {
+                target: variable("x"),
+                operator: None,
+                value: Box::new(ternary(variable("a"), variable("b"), variable("c"))),
+            }
+        );
+    }
+
+    #[test]
+    fn null_and_super() {
+        let (expression, errors) = parse_expression("a != null && a.x");
+        assert!(errors.is_empty(), "{:?}", errors);
+        match *expression.unwrap() {
+            ast::Expression::BinaryOperation { lhs, .. } => match *lhs {
+                ast::Expression::BinaryOperation { rhs, .. } => {
+                    assert_eq!(*rhs, ast::Expression::Null)
+                }
+                other => panic!("Expected a comparison, got {:?}", other),
+            },
+            other => panic!("Expected &&, got {:?}", other),
+        }
+
+        for source in ["super(1)", "super.f()", "super.x = 1"] {
+            let (expression, errors) = parse_expression(source);
+            assert!(errors.is_empty(), "{}: {:?}", source, errors);
+            assert!(expression.is_some());
+        }
+    }
+}
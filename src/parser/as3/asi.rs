@@ -0,0 +1,322 @@
+//! Automatic semicolon insertion, as described in section 7.9 of ECMA-262.
+//!
+//! Most semicolons are inserted by the grammar, which accepts an error in place of a `;` as long as
+//! [`can_insert_semicolon`] agrees. Restricted productions can not be handled that way, since the code
+//! is valid without a semicolon, so [`AutomaticSemicolons`] inserts those into the token stream.
+
+use crate::lexer::{as3::Token, LexicalError, Spanned};
+use lalrpop_util::{ErrorRecovery, ParseError};
+
+/// Inserts semicolons for restricted productions, where a line break is not allowed.
+///
+/// - `return`, `break`, `continue` and `throw` end the statement if a line break follows them.
+/// - `++` and `--` after a line break are prefix operators of the next statement.
+pub struct AutomaticSemicolons<'input, I> {
+    source: &'input str,
+    tokens: I,
+    previous: Option<PreviousToken>,
+    /// A token that is held back, since a semicolon was inserted in front of it.
+    pending: Option<(usize, Token, usize)>,
+    /// Whether the next `(` starts the head of an `if`, `while`, `for` or `with` statement.
+    statement_head: bool,
+    /// For every open parenthesis, whether it is the head of a statement.
+    parentheses: Vec<bool>,
+}
+
+/// The parts of the previous token that matter for restricted productions.
+#[derive(Clone, Copy)]
+struct PreviousToken {
+    end: usize,
+    /// Whether a line break after the token ends the statement.
+    restricted: bool,
+    ends_expression: bool,
+}
+
+impl<'input, I> AutomaticSemicolons<'input, I> {
+    pub fn new(source: &'input str, tokens: I) -> Self {
+        Self {
+            source,
+            tokens,
+            previous: None,
+            pending: None,
+            statement_head: false,
+            parentheses: Vec::new(),
+        }
+    }
+}
+
+impl<'input, I> Iterator for AutomaticSemicolons<'input, I>
+where
+    I: Iterator<Item = Spanned<Token, usize, LexicalError>>,
+{
+    type Item = Spanned<Token, usize, LexicalError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let (start, token, end) = match self.pending.take() {
+            Some(token) => token,
+            None => match self.tokens.next()? {
+                Ok(token) => token,
+                Err(e) => return Some(Err(e)),
+            },
+        };
+
+        if let Some(previous) = self.previous {
+            let insert = match token {
+                Token::Semicolon => false,
+                Token::OperatorIncrement | Token::OperatorDecrement => previous.ends_expression,
+                _ => previous.restricted,
+            };
+
+            if insert && has_line_terminator(&self.source[previous.end..start]) {
+                self.pending = Some((start, token, end));
+                self.previous = None;
+
+                return Some(Ok((previous.end, Token::Semicolon, previous.end)));
+            }
+        }
+
+        // The `)` of a statement head is followed by the body, not by a postfix operator.
+        let mut ends_expression = token.ends_expression();
+        match token {
+            Token::LParen => self.parentheses.push(self.statement_head),
+            Token::RParen => ends_expression &= !self.parentheses.pop().unwrap_or(false),
+            _ => {}
+        }
+        self.statement_head = match token {
+            Token::KeywordIf | Token::KeywordWhile | Token::KeywordFor | Token::KeywordWith => true,
+            // `for each (...)`
+            Token::KeywordEach => self.statement_head,
+            _ => false,
+        };
+
+        self.previous = Some(PreviousToken {
+            end,
+            restricted: matches!(
+                token,
+                Token::KeywordReturn
+                    | Token::KeywordBreak
+                    | Token::KeywordContinue
+                    | Token::KeywordThrow
+            ),
+            ends_expression,
+        });
+
+        Some(Ok((start, token, end)))
+    }
+}
+
+/// Checks if a semicolon can be inserted in place of a parse error.
+///
+/// `start..end` is the span lalrpop gave to the error, which covers the gap between the last token and
+/// the offending token if no input had to be thrown away.
+pub fn can_insert_semicolon(
+    source: &str,
+    recovery: &ErrorRecovery<usize, Token, LexicalError>,
+    start: usize,
+    end: usize,
+) -> bool {
+    if !recovery.dropped_tokens.is_empty() {
+        return false;
+    }
+
+    let gap = &source[start..end];
+
+    // If any part of the statement was thrown away the gap contains more than whitespace and comments.
+    if !is_trivia(gap) {
+        return false;
+    }
+
+    match &recovery.error {
+        ParseError::UnrecognizedEof { .. } => true,
+        ParseError::UnrecognizedToken {
+            token: (token_start, token, _),
+            ..
+        } => {
+            *token_start == end
+                && (matches!(token, Token::RCurlyBracket) || has_line_terminator(gap))
+        }
+        _ => false,
+    }
+}
+
+fn has_line_terminator(text: &str) -> bool {
+    text.contains(['\n', '\r', '\u{2028}', '\u{2029}'])
+}
+
+/// Checks if text only consists of whitespace and comments.
+fn is_trivia(mut text: &str) -> bool {
+    loop {
+        text = text.trim_start();
+
+        if let Some(rest) = text.strip_prefix("//") {
+            text = rest.find(['\n', '\r']).map_or("", |end| &rest[end..]);
+        } else if let Some(rest) = text.strip_prefix("/*") {
+            match rest.find("*/") {
+                Some(end) => text = &rest[end + 2..],
+                None => return false,
+            }
+        } else {
+            return text.is_empty();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lexer::Lexer;
+
+    /// Runs a source through the lexer and semicolon insertion.
+    fn tokens(source: &str) -> Vec<String> {
+        AutomaticSemicolons::new(source, Lexer::<Token>::new(source))
+            .map(|token| format!("{:?}", token.unwrap().1))
+            .collect()
+    }
+
+    /// An error at the token `token` starting at `token_start`, after the previous token ended at `start`.
+    fn can_insert(source: &str, start: usize, token_start: usize, token: Token) -> bool {
+        let recovery = ErrorRecovery {
+            error: ParseError::UnrecognizedToken {
+                token: (token_start, token, token_start + 1),
+                expected: Vec::new(),
+            },
+            dropped_tokens: Vec::new(),
+        };
+        can_insert_semicolon(source, &recovery, start, token_start)
+    }
+
+    #[test]
+    fn line_break_in_gap() {
+        let identifier = || Token::Identifier("b".to_owned());
+
+        assert!(can_insert("a\nb", 1, 2, identifier()));
+        assert!(can_insert("a\r\nb", 1, 3, identifier()));
+        assert!(can_insert("a // c\r\nb", 1, 8, identifier()));
+        assert!(can_insert("a /* c\nd */ b", 1, 12, identifier()));
+        assert!(!can_insert("a /* c */ b", 1, 10, identifier()));
+        assert!(!can_insert("a b", 1, 2, identifier()));
+    }
+
+    #[test]
+    fn closing_brace_and_eof() {
+        assert!(can_insert("a }", 1, 2, Token::RCurlyBracket));
+
+        let recovery = ErrorRecovery {
+            error: ParseError::UnrecognizedEof {
+                location: 1,
+                expected: Vec::new(),
+            },
+            dropped_tokens: Vec::new(),
+        };
+        assert!(can_insert_semicolon("a ", &recovery, 1, 2));
+    }
+
+    #[test]
+    fn discarded_input() {
+        // The gap is only trivia if nothing was thrown away.
+        assert!(!can_insert(
+            "a x\nb",
+            1,
+            4,
+            Token::Identifier("b".to_owned())
+        ));
+        assert!(!can_insert(
+            "a /* c\nb",
+            1,
+            8,
+            Token::Identifier("b".to_owned())
+        ));
+
+        let recovery = ErrorRecovery {
+            error: ParseError::UnrecognizedToken {
+                token: (2, Token::Identifier("b".to_owned()), 3),
+                expected: Vec::new(),
+            },
+            dropped_tokens: vec![(2, Token::Identifier("b".to_owned()), 3)],
+        };
+        assert!(!can_insert_semicolon("a\nb", &recovery, 1, 2));
+    }
+
+    #[test]
+    fn restricted_productions() {
+        assert_eq!(
+            tokens("return\r\nx"),
+            ["KeywordReturn", "Semicolon", "Identifier(\"x\")"]
+        );
+        assert_eq!(
+            tokens("throw /*\n*/ x"),
+            ["KeywordThrow", "Semicolon", "Identifier(\"x\")"]
+        );
+        assert_eq!(
+            tokens("return /* */ x"),
+            ["KeywordReturn", "Identifier(\"x\")"]
+        );
+    }
+
+    #[test]
+    fn postfix_after_line_break() {
+        assert_eq!(
+            tokens("a\r\n++b"),
+            [
+                "Identifier(\"a\")",
+                "Semicolon",
+                "OperatorIncrement",
+                "Identifier(\"b\")"
+            ]
+        );
+        assert_eq!(
+            tokens("a /* */ --"),
+            ["Identifier(\"a\")", "OperatorDecrement"]
+        );
+        // Contextual keywords are names, so they end an expression too.
+        assert_eq!(
+            tokens("get\n++b"),
+            [
+                "KeywordGet",
+                "Semicolon",
+                "OperatorIncrement",
+                "Identifier(\"b\")"
+            ]
+        );
+        // Nothing is inserted if the previous token can't end an expression.
+        assert_eq!(
+            tokens("=\n++b"),
+            ["Assign", "OperatorIncrement", "Identifier(\"b\")"]
+        );
+    }
+
+    #[test]
+    fn prefix_after_statement_head() {
+        // The body of these statements can start with a prefix operator on the next line.
+        for source in [
+            "if (x)\n++i",
+            "while (f(x))\n++i",
+            "for (i = 0; i < n; i++)\n--n",
+            "for each (v in (o))\n--n",
+            "with (o)\n++i",
+        ] {
+            let semicolons = tokens(source)
+                .into_iter()
+                .filter(|token| token == "Semicolon")
+                .count();
+            assert_eq!(semicolons, source.matches(';').count(), "{}", source);
+        }
+
+        // Other parentheses end an expression.
+        assert_eq!(
+            tokens("if (x) (a)\n++b"),
+            [
+                "KeywordIf",
+                "LParen",
+                "Identifier(\"x\")",
+                "RParen",
+                "LParen",
+                "Identifier(\"a\")",
+                "RParen",
+                "Semicolon",
+                "OperatorIncrement",
+                "Identifier(\"b\")"
+            ]
+        );
+    }
+}
@@ -0,0 +1,292 @@
+//! The `include` directive, which textually splices another file into the including one.
+//!
+//! Includes are resolved before parsing, by building a [`SourceMap`] whose combined source contains every included
+//! file right after the directive that includes it. The directives themselves are blanked out, so the lexer never
+//! sees them, but diagnostics can still point at them.
+
+use crate::diagnostic::Diagnostic;
+use crate::lexer::{as3::Token, Lexer};
+use crate::source_map::SourceMap;
+use std::{
+    ops::Range,
+    path::{Path, PathBuf},
+};
+
+/// An `include "path";` directive found in a file.
+struct Directive {
+    /// Range of the whole directive, including the optional semicolon.
+    span: Range<usize>,
+    /// Range of the string literal holding the path.
+    path_span: Range<usize>,
+    path: String,
+}
+
+/// Loads a file and every file it includes into a source map.
+///
+/// Included paths are resolved relative to the including file. Files that can not be included are reported, and
+/// left out of the combined source.
+pub fn load(path: &Path, source: String) -> (SourceMap, Vec<Diagnostic>) {
+    let mut loader = Loader {
+        source_map: SourceMap::default(),
+        diagnostics: Vec::new(),
+        stack: Vec::new(),
+    };
+    loader.load_file(path, source, None);
+
+    (loader.source_map, loader.diagnostics)
+}
+
+struct Loader {
+    source_map: SourceMap,
+    diagnostics: Vec<Diagnostic>,
+    /// Files that are currently being included, used to detect include cycles. Canonical paths are compared, the
+    /// displayed ones are reported.
+    stack: Vec<(PathBuf, String)>,
+}
+
+impl Loader {
+    fn load_file(&mut self, path: &Path, source: String, included_from: Option<Range<usize>>) {
+        let directives = find_directives(&source);
+        let len = source.len();
+        let file = self
+            .source_map
+            .add_file(path.display().to_string(), source, included_from);
+
+        self.stack
+            .push((canonicalize(path), path.display().to_string()));
+
+        let mut position = 0;
+        for directive in directives {
+            self.source_map.append(file, position..directive.span.start);
+            let site = self.source_map.append_blank(file, directive.span.clone());
+
+            // Offsets within the directive are the same in the file and the combined source.
+            let path_span = site.start + directive.path_span.start - directive.span.start
+                ..site.start + directive.path_span.end - directive.span.start;
+            self.include(path, &directive.path, site, path_span);
+
+            // The rest of the including file starts on a new line.
+            self.source_map.append_separator(file, directive.span.end);
+
+            position = directive.span.end;
+        }
+        self.source_map.append(file, position..len);
+
+        self.stack.pop();
+    }
+
+    fn include(
+        &mut self,
+        including: &Path,
+        path: &str,
+        site: Range<usize>,
+        path_span: Range<usize>,
+    ) {
+        let path = including
+            .parent()
+            .unwrap_or_else(|| Path::new(""))
+            .join(path);
+
+        let canonical = canonicalize(&path);
+        if let Some(start) = self.stack.iter().position(|(file, _)| *file == canonical) {
+            let cycle: Vec<_> = self.stack[start..]
+                .iter()
+                .map(|(_, name)| name.clone())
+                .chain([path.display().to_string()])
+                .collect();
+            self.diagnostics.push(
+                Diagnostic::error(format!("include cycle: {}", cycle.join(" → ")))
+                    .with_primary(path_span, "recursive include"),
+            );
+            return;
+        }
+
+        let source = match std::fs::read_to_string(&path) {
+            Ok(source) => source,
+            Err(e) => {
+                self.diagnostics.push(
+                    Diagnostic::error(format!("could not read {}: {}", path.display(), e))
+                        .with_primary(path_span, "included here"),
+                );
+                return;
+            }
+        };
+
+        self.load_file(&path, source, Some(site));
+    }
+}
+
+/// Finds all include directives in a file.
+fn find_directives(source: &str) -> Vec<Directive> {
+    let mut directives = Vec::new();
+    let mut tokens = Lexer::<Token>::new(source)
+        .filter_map(Result::ok)
+        .peekable();
+
+    while let Some((start, token, _)) = tokens.next() {
+        if !matches!(token, Token::KeywordInclude) {
+            continue;
+        }
+
+        // Outside of a directive `include` is a normal identifier.
+        let Some((path_start, Token::String(path), path_end)) =
+            tokens.next_if(|(_, token, _)| matches!(token, Token::String(_)))
+        else {
+            continue;
+        };
+
+        let end = match tokens.next_if(|(_, token, _)| matches!(token, Token::Semicolon)) {
+            Some((_, _, end)) => end,
+            None => path_end,
+        };
+
+        directives.push(Directive {
+            span: start..end,
+            path_span: path_start..path_end,
+            path,
+        });
+    }
+
+    directives
+}
+
+/// Gives every file a unique path, so a cycle is detected even if a file is included through different paths.
+fn canonicalize(path: &Path) -> PathBuf {
+    path.canonicalize().unwrap_or_else(|_| path.to_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_dir(test: &str) -> PathBuf {
+        std::env::temp_dir().join(format!(
+            "as3_compiler_include_{}_{}",
+            test,
+            std::process::id()
+        ))
+    }
+
+    /// Writes the files into the directory of the test and loads the first one.
+    fn load_files(test: &str, files: &[(&str, &str)]) -> (SourceMap, Vec<Diagnostic>) {
+        let dir = test_dir(test);
+        let _ = std::fs::remove_dir_all(&dir);
+        for (name, source) in files {
+            let path = dir.join(name);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(&path, source).unwrap();
+        }
+
+        let (name, source) = files[0];
+        let loaded = load(&dir.join(name), source.to_owned());
+        std::fs::remove_dir_all(&dir).unwrap();
+        loaded
+    }
+
+    /// The statements of the combined source, directives are blanked out.
+    fn statements(source_map: &SourceMap) -> Vec<&str> {
+        source_map.source().split_whitespace().collect()
+    }
+
+    #[test]
+    fn self_include() {
+        let (source_map, diagnostics) = load_files("self", &[("a.as", "include \"a.as\";\na;")]);
+
+        assert_eq!(statements(&source_map), ["a;"]);
+        assert_eq!(diagnostics.len(), 1);
+        let a = test_dir("self").join("a.as");
+        assert_eq!(
+            diagnostics[0].message,
+            format!("include cycle: {} → {}", a.display(), a.display())
+        );
+    }
+
+    #[test]
+    fn include_cycle() {
+        let (source_map, diagnostics) = load_files(
+            "cycle",
+            &[
+                ("a.as", "include \"b.as\";\na;"),
+                ("b.as", "include \"a.as\";\nb;"),
+            ],
+        );
+
+        // Every file is only included once, the cycle is reported where it is closed.
+        assert_eq!(statements(&source_map), ["b;", "a;"]);
+        assert_eq!(diagnostics.len(), 1);
+        let [a, b] = ["a.as", "b.as"].map(|name| test_dir("cycle").join(name));
+        assert_eq!(
+            diagnostics[0].message,
+            format!(
+                "include cycle: {} → {} → {}",
+                a.display(),
+                b.display(),
+                a.display()
+            )
+        );
+        let span = &diagnostics[0].primary.as_ref().unwrap().span;
+        let (file, _) = source_map.lookup(span.start);
+        assert!(source_map.file(file).name().ends_with("b.as"));
+    }
+
+    #[test]
+    fn nested_include_is_relative_to_its_file() {
+        let (source_map, diagnostics) = load_files(
+            "nested",
+            &[
+                ("a.as", "x;\ninclude \"lib/b.as\";\ny;"),
+                ("lib/b.as", "include \"c.as\"\nz;"),
+                ("lib/c.as", "w;"),
+            ],
+        );
+
+        assert!(diagnostics.is_empty(), "{:?}", diagnostics);
+        assert_eq!(statements(&source_map), ["x;", "w;", "z;", "y;"]);
+    }
+
+    #[test]
+    fn missing_file() {
+        let (source_map, diagnostics) =
+            load_files("missing", &[("a.as", "include \"missing.as\";\na;")]);
+
+        assert_eq!(statements(&source_map), ["a;"]);
+        assert_eq!(diagnostics.len(), 1);
+        assert!(diagnostics[0].message.starts_with("could not read"));
+        assert_eq!(
+            diagnostics[0].primary.as_ref().unwrap().message,
+            "included here"
+        );
+    }
+
+    #[test]
+    fn render_include_sites() {
+        let (source_map, _) = load_files(
+            "render",
+            &[
+                ("a.as", "x;\ninclude \"lib/b.as\";\ny;"),
+                ("lib/b.as", "include \"c.as\"\nz;"),
+                ("lib/c.as", "w;"),
+            ],
+        );
+        let offset = source_map.source().find('w').unwrap();
+        let rendered = Diagnostic::error("boom")
+            .with_primary(offset..offset + 1, "here")
+            .render(&source_map)
+            .replace(&format!("{}/", test_dir("render").display()), "");
+
+        assert_eq!(
+            rendered,
+            "error: boom\n \
+             --> lib/c.as:1:1\n  \
+             |\n\
+             1 | w;\n  \
+             | ^ here\n \
+             ::: lib/b.as:1:1\n\
+             1 | include \"c.as\"\n  \
+             | -------------- included here\n \
+             ::: a.as:2:1\n\
+             2 | include \"lib/b.as\";\n  \
+             | ------------------- included here\n"
+        );
+    }
+}
@@ -1,6 +1,8 @@
 use crate::parser::common::Type;
 use swf::avm2::types::Op;
 
+// Generated by lalrpop, which exports every parser and does not produce clippy clean code.
+#[allow(clippy::all, unused_imports)]
 pub mod grammar;
 
 /// Represents a Id source, which can be anything (e.g. .function_id).
@@ -1,11 +1,12 @@
 use std::{convert::Infallible, str::FromStr};
 
-#[derive(Debug, PartialEq, Eq)]
+#[derive(Clone, Debug, PartialEq, Eq)]
 pub enum Type {
     Any,
     Void,
     Int,
     Uint,
+    Number,
     Bool,
     Other(String),
 }
@@ -19,6 +20,7 @@ impl FromStr for Type {
             "void" => Ok(Type::Void),
             "int" => Ok(Type::Int),
             "uint" => Ok(Type::Uint),
+            "Number" => Ok(Type::Number),
             "Boolean" => Ok(Type::Bool),
             s => Ok(Type::Other(s.to_owned())),
         }
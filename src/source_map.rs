@@ -0,0 +1,336 @@
+//! Conversion of byte offsets into human readable locations.
+//!
+//! A [`SourceMap`] combines the main file with every file it includes into a single source, so the lexer and parser
+//! can work with plain byte offsets. Offsets into the combined source are mapped back to the [`SourceFile`] they
+//! came from when a location is shown to the user.
+
+use std::{fmt, ops::Range};
+
+/// A human readable position in a source file, both line and column start at 1.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Location {
+    pub line: usize,
+    pub column: usize,
+}
+
+impl fmt::Display for Location {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}", self.line, self.column)
+    }
+}
+
+/// Identifies a file in a [`SourceMap`].
+pub type FileId = usize;
+
+/// Owns a source file and maps byte offsets into it to line/column pairs.
+#[derive(Debug)]
+pub struct SourceFile {
+    name: String,
+    source: String,
+    /// Byte offset of the first character of every line.
+    line_starts: Vec<usize>,
+    /// Range of the include directive in the combined source, if this file was included by another one.
+    included_from: Option<Range<usize>>,
+}
+
+impl SourceFile {
+    fn new(name: String, source: String, included_from: Option<Range<usize>>) -> Self {
+        let mut line_starts = vec![0];
+        let bytes = source.as_bytes();
+
+        for (index, byte) in bytes.iter().enumerate() {
+            match byte {
+                b'\n' => line_starts.push(index + 1),
+                // A lone carriage return also terminates a line, but "\r\n" only counts once.
+                b'\r' if bytes.get(index + 1) != Some(&b'\n') => line_starts.push(index + 1),
+                _ => {}
+            }
+        }
+
+        Self {
+            name,
+            source,
+            line_starts,
+            included_from,
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn source(&self) -> &str {
+        &self.source
+    }
+
+    pub fn included_from(&self) -> Option<Range<usize>> {
+        self.included_from.clone()
+    }
+
+    /// Converts a byte offset into a line/column pair.
+    ///
+    /// Columns are counted in characters, offsets past the end of the file point just after the last character.
+    pub fn location(&self, offset: usize) -> Location {
+        let offset = offset.min(self.source.len());
+        let line = match self.line_starts.binary_search(&offset) {
+            Ok(line) => line,
+            Err(line) => line - 1,
+        };
+
+        let line_start = self.line_starts[line];
+        let column = self.source[line_start..offset].chars().count();
+
+        Location {
+            line: line + 1,
+            column: column + 1,
+        }
+    }
+
+    /// Byte range of a line (starting at 1), excluding the line terminator.
+    pub fn line_span(&self, line: usize) -> Range<usize> {
+        let start = self.line_starts[line - 1];
+        let end = self
+            .line_starts
+            .get(line)
+            .copied()
+            .unwrap_or(self.source.len());
+        let text = &self.source[start..end];
+        let trimmed = text.trim_end_matches(['\n', '\r']);
+
+        start..start + trimmed.len()
+    }
+
+    /// Text of a line (starting at 1), excluding the line terminator.
+    pub fn line(&self, line: usize) -> &str {
+        &self.source[self.line_span(line)]
+    }
+}
+
+/// A part of the combined source that was copied from a file.
+#[derive(Debug)]
+struct Segment {
+    /// Offset of the segment in the combined source.
+    start: usize,
+    file: FileId,
+    /// Range of the file that the segment was copied from, empty for inserted separators.
+    file_span: Range<usize>,
+}
+
+/// Owns all source files of a compilation and the combined source that is handed to the lexer.
+#[derive(Debug, Default)]
+pub struct SourceMap {
+    files: Vec<SourceFile>,
+    source: String,
+    segments: Vec<Segment>,
+}
+
+impl SourceMap {
+    /// Creates a source map of a single file.
+    pub fn new(name: impl Into<String>, source: String) -> Self {
+        let mut source_map = Self::default();
+        let len = source.len();
+        let file = source_map.add_file(name, source, None);
+        source_map.append(file, 0..len);
+        source_map
+    }
+
+    /// Adds a file without adding any of its contents to the combined source.
+    pub fn add_file(
+        &mut self,
+        name: impl Into<String>,
+        source: String,
+        included_from: Option<Range<usize>>,
+    ) -> FileId {
+        self.files
+            .push(SourceFile::new(name.into(), source, included_from));
+        self.files.len() - 1
+    }
+
+    /// Copies a range of a file to the end of the combined source.
+    pub fn append(&mut self, file: FileId, span: Range<usize>) {
+        self.push_segment(file, span.clone());
+        self.source.push_str(&self.files[file].source[span]);
+    }
+
+    /// Copies a range of a file to the end of the combined source, replacing everything but line terminators with
+    /// spaces, so the lexer skips it while locations stay the same.
+    ///
+    /// Returns the range in the combined source.
+    pub fn append_blank(&mut self, file: FileId, span: Range<usize>) -> Range<usize> {
+        let start = self.push_segment(file, span.clone());
+        for c in self.files[file].source[span].chars() {
+            match c {
+                '\n' | '\r' => self.source.push(c),
+                c => self.source.extend(std::iter::repeat_n(' ', c.len_utf8())),
+            }
+        }
+        start..self.source.len()
+    }
+
+    /// Inserts a line break into the combined source, which is located at `offset` of a file.
+    ///
+    /// This keeps the tokens of two spliced files from running into each other.
+    pub fn append_separator(&mut self, file: FileId, offset: usize) {
+        self.push_segment(file, offset..offset);
+        self.source.push('\n');
+    }
+
+    fn push_segment(&mut self, file: FileId, file_span: Range<usize>) -> usize {
+        let start = self.source.len();
+        self.segments.push(Segment {
+            start,
+            file,
+            file_span,
+        });
+        start
+    }
+
+    /// The combined source of all files.
+    pub fn source(&self) -> &str {
+        &self.source
+    }
+
+    pub fn file(&self, file: FileId) -> &SourceFile {
+        &self.files[file]
+    }
+
+    /// Finds the file that an offset into the combined source belongs to, and the offset within that file.
+    pub fn lookup(&self, offset: usize) -> (FileId, usize) {
+        // Empty segments share their start with the next one, the last of them is the one that contains text.
+        let index = self
+            .segments
+            .partition_point(|segment| segment.start <= offset)
+            .saturating_sub(1);
+        let segment = &self.segments[index];
+        let len = segment.file_span.end - segment.file_span.start;
+
+        (
+            segment.file,
+            segment.file_span.start + (offset - segment.start).min(len),
+        )
+    }
+
+    /// Converts an offset into the combined source into a line/column pair of the file it belongs to.
+    pub fn location(&self, offset: usize) -> Location {
+        let (file, offset) = self.lookup(offset);
+        self.files[file].location(offset)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn location(line: usize, column: usize) -> Location {
+        Location { line, column }
+    }
+
+    #[test]
+    fn line_terminators() {
+        let file = SourceFile::new("test.as".to_owned(), "a\r\nb\rc\nd".to_owned(), None);
+
+        assert_eq!(file.location(0), location(1, 1));
+        // Both characters of "\r\n" belong to the first line.
+        assert_eq!(file.location(1), location(1, 2));
+        assert_eq!(file.location(2), location(1, 3));
+        assert_eq!(file.location(3), location(2, 1));
+        assert_eq!(file.location(5), location(3, 1));
+        assert_eq!(file.location(7), location(4, 1));
+        assert_eq!(file.line(1), "a");
+        assert_eq!(file.line(2), "b");
+        assert_eq!(file.line(3), "c");
+        assert_eq!(file.line(4), "d");
+    }
+
+    #[test]
+    fn block_comment_spanning_lines() {
+        let source = "x /* one\r\ntwo\nthree */ y";
+        let file = SourceFile::new("test.as".to_owned(), source.to_owned(), None);
+
+        assert_eq!(file.location(source.find('y').unwrap()), location(3, 10));
+        assert_eq!(file.line(2), "two");
+    }
+
+    #[test]
+    fn multibyte_columns() {
+        let source = "é𝄞x\n\u{2028}y";
+        let file = SourceFile::new("test.as".to_owned(), source.to_owned(), None);
+
+        // Columns count characters, not bytes.
+        assert_eq!(file.location(source.find('x').unwrap()), location(1, 3));
+        // Only CR and LF start a new line.
+        assert_eq!(file.location(source.find('y').unwrap()), location(2, 2));
+    }
+
+    #[test]
+    fn offset_past_end() {
+        let file = SourceFile::new("test.as".to_owned(), "ab\n".to_owned(), None);
+
+        assert_eq!(file.location(3), location(2, 1));
+        assert_eq!(file.location(100), location(2, 1));
+    }
+
+    /// Splices `b.as` into `a.as` the same way an include directive does.
+    fn spliced() -> SourceMap {
+        let a = "x;\ninclude \"b.as\";\ny;".to_owned();
+        let b = "z;".to_owned();
+        let directive = a.find("include").unwrap()..a.find("\";").unwrap() + 2;
+
+        let mut source_map = SourceMap::default();
+        let a_len = a.len();
+        let a_id = source_map.add_file("a.as", a, None);
+        source_map.append(a_id, 0..directive.start);
+        let site = source_map.append_blank(a_id, directive.clone());
+        let b_id = source_map.add_file("b.as", b, Some(site));
+        source_map.append(b_id, 0..2);
+        source_map.append_separator(a_id, directive.end);
+        source_map.append(a_id, directive.end..a_len);
+
+        source_map
+    }
+
+    #[test]
+    fn spliced_segments() {
+        let source_map = spliced();
+        let source = source_map.source();
+        let blank = " ".repeat("include \"b.as\";".len());
+        assert_eq!(source, format!("x;\n{}z;\n\ny;", blank));
+
+        let included = source.find('z').unwrap();
+        let directive_end = included - 1;
+
+        // The blanked directive still maps to the including file.
+        assert_eq!(source_map.lookup(3), (0, 3));
+        assert_eq!(source_map.lookup(directive_end), (0, directive_end));
+        // The first and last character of the included file.
+        assert_eq!(source_map.lookup(included), (1, 0));
+        assert_eq!(source_map.lookup(included + 1), (1, 1));
+        // The inserted separator is located at the end of the directive.
+        assert_eq!(source_map.lookup(included + 2), (0, directive_end + 1));
+        // The rest of the including file continues where the directive ended.
+        assert_eq!(source_map.lookup(included + 3), (0, directive_end + 1));
+        assert_eq!(
+            source_map.location(source.find('y').unwrap()),
+            location(3, 1)
+        );
+        assert_eq!(source_map.location(included), location(1, 1));
+        assert_eq!(
+            source_map.file(1).included_from(),
+            Some(3..directive_end + 1)
+        );
+    }
+
+    #[test]
+    fn empty_segments() {
+        let mut source_map = SourceMap::default();
+        let a = source_map.add_file("a.as", "ab".to_owned(), None);
+        let b = source_map.add_file("b.as", String::new(), None);
+        source_map.append(a, 0..1);
+        source_map.append(b, 0..0);
+        source_map.append(a, 1..2);
+
+        // The empty segment is skipped in favour of the one that contains the character.
+        assert_eq!(source_map.lookup(1), (a, 1));
+        assert_eq!(source_map.lookup(2), (a, 2));
+    }
+}